@@ -0,0 +1,143 @@
+use raylib::camera::Camera2D;
+
+use crate::components::{Hitbox, Position};
+
+pub const DEADZONE_WIDTH: f32 = 96.;
+pub const DEADZONE_HEIGHT: f32 = 128.;
+pub const LOOKAHEAD: f32 = 64.;
+// how quickly the lookahead swings over when the player turns, per second
+const LOOKAHEAD_RATE: f32 = 3.;
+const MIN_ZOOM: f32 = 0.25;
+const MAX_ZOOM: f32 = 4.;
+const ZOOM_STEP: f32 = 1.1;
+
+// Where the world is viewed from. `target` is the world point drawn at `offset` on screen.
+#[derive(Debug, Clone)]
+pub struct Camera {
+    pub target: Position,
+    pub offset: Position,
+    pub zoom: f32,
+    // the player can move this far around the target before the camera follows
+    pub deadzone: Position,
+    // how far ahead of a running player the camera looks
+    pub lookahead: f32,
+    look: f32,
+    // where the editor was looking and how far zoomed in, kept while playing
+    editor_view: Option<(Position, f32)>
+}
+
+impl Camera {
+    // Starts out lined up with the screen so world and screen coordinates match
+    pub fn new(screen_width: f32, screen_height: f32) -> Self {
+        let centre = Position::new(screen_width / 2., screen_height / 2.);
+        Camera {
+            target: centre,
+            offset: centre,
+            zoom: 1.,
+            deadzone: Position::new(DEADZONE_WIDTH, DEADZONE_HEIGHT),
+            lookahead: LOOKAHEAD,
+            look: 0.,
+            editor_view: None
+        }
+    }
+
+    pub fn screen_to_world(&self, point: Position) -> Position {
+        (point - self.offset) / self.zoom + self.target
+    }
+
+    // Drags the view along with the mouse, `delta` is in screen pixels
+    pub fn pan(&mut self, delta: Position) {
+        self.target -= delta / self.zoom;
+    }
+
+    // Zooms by wheel notches, keeping the world point under `anchor` where it is on screen
+    pub fn zoom_at(&mut self, anchor: Position, notches: f32) {
+        let before = self.screen_to_world(anchor);
+        self.zoom = (self.zoom * ZOOM_STEP.powf(notches)).clamp(MIN_ZOOM, MAX_ZOOM);
+        self.target = before - (anchor - self.offset) / self.zoom;
+    }
+
+    // Play always starts unzoomed, looking at `focus` when there is one
+    pub fn start_play(&mut self, focus: Option<Position>) {
+        self.editor_view = Some((self.target, self.zoom));
+        self.zoom = 1.;
+        self.look = 0.;
+        if let Some(focus) = focus {
+            self.target = focus;
+        }
+    }
+
+    // Puts the view back how the editor left it
+    pub fn stop_play(&mut self) {
+        if let Some((target, zoom)) = self.editor_view.take() {
+            self.target = target;
+            self.zoom = zoom;
+        }
+    }
+
+    // Keeps `focus`, pushed ahead in the direction of travel, inside the deadzone
+    pub fn follow(&mut self, focus: Position, velocity_x: f32, delta: f32) {
+        let direction = if velocity_x.abs() > 1. { velocity_x.signum() } else { 0. };
+        self.look += (direction * self.lookahead - self.look) * (LOOKAHEAD_RATE * delta).min(1.);
+        let focus = focus + Position::new(self.look, 0.);
+        let half = self.deadzone / 2.;
+        if focus.x < self.target.x - half.x {
+            self.target.x = focus.x + half.x;
+        } else if focus.x > self.target.x + half.x {
+            self.target.x = focus.x - half.x;
+        }
+        if focus.y < self.target.y - half.y {
+            self.target.y = focus.y + half.y;
+        } else if focus.y > self.target.y + half.y {
+            self.target.y = focus.y - half.y;
+        }
+    }
+
+    // Stops the view showing anything outside `bounds`; a level smaller than the view is centred
+    pub fn clamp_to(&mut self, bounds: &Hitbox) {
+        let half = self.offset / self.zoom;
+        self.target.x = clamp_axis(self.target.x, half.x, bounds.position.x, bounds.width);
+        self.target.y = clamp_axis(self.target.y, half.y, bounds.position.y, bounds.height);
+    }
+}
+
+fn clamp_axis(target: f32, half_view: f32, start: f32, length: f32) -> f32 {
+    if length <= half_view * 2. {
+        start + length / 2.
+    } else {
+        target.max(start + half_view).min(start + length - half_view)
+    }
+}
+
+impl From<&Camera> for Camera2D {
+    fn from(camera: &Camera) -> Camera2D {
+        Camera2D {
+            offset: camera.offset.into(),
+            target: camera.target.into(),
+            rotation: 0.,
+            zoom: camera.zoom
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn play_unzooms_and_editing_gets_its_view_back() {
+        let mut camera = Camera::new(800., 640.);
+        camera.zoom_at(Position::new(100., 100.), 3.);
+        camera.pan(Position::new(50., 20.));
+        let (target, zoom) = (camera.target, camera.zoom);
+
+        camera.start_play(Some(Position::new(64., 288.)));
+        assert_eq!(camera.zoom, 1.);
+        assert_eq!(camera.target, Position::new(64., 288.));
+
+        camera.follow(Position::new(300., 288.), 200., 0.5);
+        camera.stop_play();
+        assert_eq!(camera.target, target);
+        assert_eq!(camera.zoom, zoom);
+    }
+}
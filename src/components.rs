@@ -1,9 +1,13 @@
-use specs::{Component, VecStorage};
+use specs::{Component, Entity, FlaggedStorage, NullStorage, VecStorage};
 use raylib::math::{Rectangle, Vector2};
 use serde::{Serialize, Deserialize};
 use raylib::consts::rIconDescription::*;
+use raylib::consts::{KeyboardKey, KeyboardKey::*, MouseButton, MouseButton::*};
+use raylib::prelude::RaylibHandle;
 
-use std::ops::{Mul, Add, AddAssign, Deref,  Sub, SubAssign, MulAssign, Div, DivAssign, Neg};
+use crate::history::SavedEntity;
+
+use std::ops::{Mul, Add, AddAssign, Sub, SubAssign, MulAssign, Div, DivAssign, Neg};
 
 #[derive(Component, Debug, PartialEq, Default, Copy, Clone, Serialize, Deserialize)]
 #[storage(VecStorage)]
@@ -22,6 +26,12 @@ impl Position {
     pub fn zero() -> Self {
         Position::new(0., 0.)
     }
+    pub fn lerp(self, other: Position, t: f32) -> Self {
+        self + (other - self) * t
+    }
+    pub fn length(self) -> f32 {
+        (self.x * self.x + self.y * self.y).sqrt()
+    }
 }
 
 impl Add for Position {
@@ -172,11 +182,11 @@ impl From<Vector2> for Position {
     }
 }
 
-impl Into<Vector2> for Position {
-    fn into(self) -> Vector2 {
+impl From<Position> for Vector2 {
+    fn from(p: Position) -> Vector2 {
         Vector2 {
-            x: self.x,
-            y: self.y
+            x: p.x,
+            y: p.y
         }
     }
 }
@@ -193,16 +203,185 @@ pub struct Transform {
 #[storage(VecStorage)]
 pub struct PlatformController {
     pub can_jump: bool,
-    pub coyote_time: f32
+    // fastest the player runs, in pixels per second
+    pub max_speed: f32,
+    // how quickly speed builds up and dies down on the ground, per second
+    pub ground_accel: f32,
+    pub ground_decel: f32,
+    // how quickly speed changes in the air, with or without a direction held
+    pub air_accel: f32,
+    // acceleration is multiplied by this when pushing against the current direction
+    pub turn_boost: f32,
+    // seconds after walking off a ledge that a jump is still allowed
+    pub coyote_time: f32,
+    // seconds before landing that a jump press is remembered
+    pub jump_buffer: f32,
+    // upward velocity is multiplied by this when jump is released early
+    pub jump_cut: f32,
+    // seconds since last on the ground
+    pub air_time: f32,
+    // seconds left on a buffered jump press
+    pub buffered: f32,
+    pub jump_held: bool,
+    // rising from a jump, so releasing the key can still cut it
+    pub jumping: bool,
+    // allows wall sliding and wall jumping
+    pub wall_abilities: bool,
+    // fastest fall while pressed against a wall
+    pub wall_slide_speed: f32,
+    // horizontal speed a wall jump pushes off with
+    pub wall_kick: f32,
+    // seconds after a wall jump that left/right are ignored
+    pub wall_lockout: f32,
+    pub wall_left: bool,
+    pub wall_right: bool,
+    // seconds left before left/right work again
+    pub lockout: f32,
+    // seconds left of falling through one-way platforms
+    pub dropping: f32,
+    pub spawn: Position,
+    // counts down while the death state plays, the controller respawns when it runs out
+    pub death_timer: f32
 }
 
+pub const MAX_SPEED: f32 = 200.;
+pub const GROUND_ACCEL: f32 = 1600.;
+pub const GROUND_DECEL: f32 = 2000.;
+pub const AIR_ACCEL: f32 = 1000.;
+pub const TURN_BOOST: f32 = 2.;
+pub const COYOTE_TIME: f32 = 0.1;
+pub const JUMP_BUFFER: f32 = 0.1;
+pub const JUMP_CUT: f32 = 0.5;
+pub const WALL_SLIDE_SPEED: f32 = 60.;
+pub const WALL_KICK: f32 = 250.;
+pub const WALL_LOCKOUT: f32 = 0.15;
+// long enough to fall clear of a one-way platform
+pub const DROP_TIME: f32 = 0.2;
+
 impl PlatformController {
-    pub fn new() -> PlatformController {
+    pub fn new(spawn: Position) -> PlatformController {
         PlatformController {
             can_jump: false,
-            coyote_time: 0.
+            max_speed: MAX_SPEED,
+            ground_accel: GROUND_ACCEL,
+            ground_decel: GROUND_DECEL,
+            air_accel: AIR_ACCEL,
+            turn_boost: TURN_BOOST,
+            coyote_time: COYOTE_TIME,
+            jump_buffer: JUMP_BUFFER,
+            jump_cut: JUMP_CUT,
+            air_time: 0.,
+            buffered: 0.,
+            jump_held: false,
+            jumping: false,
+            wall_abilities: true,
+            wall_slide_speed: WALL_SLIDE_SPEED,
+            wall_kick: WALL_KICK,
+            wall_lockout: WALL_LOCKOUT,
+            wall_left: false,
+            wall_right: false,
+            lockout: 0.,
+            dropping: 0.,
+            spawn,
+            death_timer: 0.
+        }
+    }
+
+    // Horizontal speed change allowed this step when steering towards `direction` (-1, 0 or 1)
+    pub fn acceleration(&self, velocity_x: f32, direction: f32) -> f32 {
+        let accel = if !self.can_jump {
+            self.air_accel
+        } else if direction == 0. {
+            self.ground_decel
+        } else {
+            self.ground_accel
+        };
+        if velocity_x * direction < 0. { accel * self.turn_boost } else { accel }
+    }
+
+    pub fn can_jump_now(&self) -> bool {
+        self.can_jump || self.air_time < self.coyote_time
+    }
+
+    // Which way a wall jump pushes off, none when not against a wall in the air
+    pub fn wall_jump_direction(&self) -> Option<f32> {
+        if !self.wall_abilities || self.can_jump {
+            None
+        } else if self.wall_left {
+            Some(1.)
+        } else if self.wall_right {
+            Some(-1.)
+        } else {
+            None
+        }
+    }
+
+    pub fn is_dead(&self) -> bool {
+        self.death_timer > 0.
+    }
+}
+
+pub const DASH_DISTANCE: f32 = 96.;
+pub const DASH_DURATION: f32 = 0.15;
+pub const DASH_COOLDOWN: f32 = 0.4;
+pub const AIR_DASHES: u32 = 1;
+
+// Lets a PlatformController dash a fixed distance in any of the 8 directions
+#[derive(Component, Debug, Clone, Copy)]
+#[storage(VecStorage)]
+pub struct Dash {
+    pub distance: f32,
+    pub duration: f32,
+    // seconds from the start of one dash until the next is allowed
+    pub cooldown: f32,
+    // dashes allowed before landing again
+    pub air_dashes: u32,
+    pub air_left: u32,
+    // seconds left of the current dash
+    pub time_left: f32,
+    pub cooldown_left: f32,
+    pub direction: Position,
+    // 1 facing right, -1 facing left
+    pub facing: f32,
+    pub held: bool
+}
+
+impl Default for Dash {
+    fn default() -> Self {
+        Dash::new()
+    }
+}
+
+impl Dash {
+    pub fn new() -> Self {
+        Dash {
+            distance: DASH_DISTANCE,
+            duration: DASH_DURATION,
+            cooldown: DASH_COOLDOWN,
+            air_dashes: AIR_DASHES,
+            air_left: AIR_DASHES,
+            time_left: 0.,
+            cooldown_left: 0.,
+            direction: Position::zero(),
+            facing: 1.,
+            held: false
         }
     }
+
+    // float error can leave a sliver of time after the last step
+    pub fn is_dashing(&self) -> bool {
+        self.time_left > 0.0001
+    }
+
+    pub fn velocity(&self) -> Position {
+        self.direction * (self.distance / self.duration)
+    }
+
+    pub fn reset(&mut self) {
+        self.air_left = self.air_dashes;
+        self.time_left = 0.;
+        self.cooldown_left = 0.;
+    }
 }
 
 #[derive(Component, Debug, Default, Clone, Copy)]
@@ -210,7 +389,11 @@ impl PlatformController {
 pub struct Moveable {
     pub velocity: Position,
     pub width: f32,
-    pub height: f32
+    pub height: f32,
+    // position at the start of the last simulation step, used to interpolate drawing
+    pub previous: Option<Position>,
+    // what it stood on last step, plain ground while in the air
+    pub ground: Surface
 }
 
 impl Moveable {
@@ -218,7 +401,9 @@ impl Moveable {
         Moveable {
             velocity: Position::new(0., 0.),
             width: 32.,
-            height: 32.
+            height: 32.,
+            previous: None,
+            ground: Surface::default()
         }
     }
     pub fn to_hitbox(&self, position: Position) -> Hitbox {
@@ -230,31 +415,270 @@ impl Moveable {
     }
 }
 
-#[derive(Component, Debug, Default, Clone, Copy)]
+// about as long as the old 80 step countdown
+pub const FALL_DELAY: f32 = 1.35;
+// how far a block shakes either side while it counts down, and how fast
+pub const SHAKE_DISTANCE: f32 = 2.;
+pub const SHAKE_SPEED: f32 = 40.;
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum FallState {
+    #[default]
+    Resting,
+    // seconds since something stepped on it
+    Shaking(f32),
+    Falling,
+    // seconds since it left the level
+    Gone(f32)
+}
+
+// A block that falls a while after the player steps on it
+#[derive(Component, Debug, Clone, Copy, Serialize, Deserialize)]
 #[storage(VecStorage)]
 pub struct FallingBlock {
-    pub count: u32,
-    pub should_fall: bool
+    // seconds it shakes before falling
+    pub delay: f32,
+    // seconds after leaving the level that it comes back where it was placed, never if None
+    pub respawn: Option<f32>,
+    #[serde(skip)]
+    pub state: FallState,
+    // where it was placed, taken when it starts shaking
+    #[serde(skip)]
+    pub home: Option<Hitbox>
 }
 
-impl FallingBlock {
-    pub fn default() -> Self {
+impl Default for FallingBlock {
+    fn default() -> Self {
         FallingBlock {
-            count: 0,
-            should_fall: false,
+            delay: FALL_DELAY,
+            respawn: None,
+            state: FallState::Resting,
+            home: None
+        }
+    }
+}
+
+impl FallingBlock {
+    // The strip that sets it off: anything overlapping it is standing on the block
+    pub fn trigger_area(hitbox: &Hitbox) -> Hitbox {
+        Hitbox {
+            position: Position::new(hitbox.position.x, hitbox.position.y - 1.),
+            ..*hitbox
+        }
+    }
+
+    // How far to draw it from where it is, so it wobbles while counting down
+    pub fn shake(&self) -> f32 {
+        match self.state {
+            FallState::Shaking(time) => (time * SHAKE_SPEED).sin() * SHAKE_DISTANCE,
+            _ => 0.
+        }
+    }
+}
+
+// Where the player is put when play mode starts and after dying
+#[derive(Component, Debug, Default, Clone, Copy, Serialize, Deserialize)]
+#[storage(NullStorage)]
+pub struct SpawnPoint;
+
+// Kills any PlatformController that touches it
+#[derive(Component, Debug, Default, Clone, Copy, Serialize, Deserialize)]
+#[storage(NullStorage)]
+pub struct Hazard;
+
+pub const PLATFORM_SPEED: f32 = 64.;
+pub const PLATFORM_PAUSE: f32 = 0.5;
+
+// A solid block that travels along a path of waypoints, carrying whatever stands on it.
+// Waypoints are offsets from where the platform was placed, so dragging it moves the whole path.
+#[derive(Component, Debug, Clone, Serialize, Deserialize)]
+#[storage(VecStorage)]
+pub struct MovingPlatform {
+    pub waypoints: Vec<Position>,
+    // go round from the last waypoint back to the start instead of turning back
+    pub looping: bool,
+    pub speed: f32,
+    // seconds spent waiting at each waypoint
+    pub pause: f32,
+    // where the path starts, taken from the hitbox the first time the platform moves
+    #[serde(skip)]
+    pub origin: Option<Position>,
+    #[serde(skip)]
+    pub target: usize,
+    #[serde(skip)]
+    pub reverse: bool,
+    #[serde(skip)]
+    pub wait: f32,
+    // where it was at the start of the last step, so it can be drawn between steps like riders are
+    #[serde(skip)]
+    pub previous: Option<Position>
+}
+
+impl Default for MovingPlatform {
+    fn default() -> Self {
+        MovingPlatform {
+            waypoints: Vec::new(),
+            looping: false,
+            speed: PLATFORM_SPEED,
+            pause: PLATFORM_PAUSE,
+            origin: None,
+            target: 0,
+            reverse: false,
+            wait: 0.,
+            previous: None
+        }
+    }
+}
+
+impl MovingPlatform {
+    // Every stop along the path, starting with where the platform was placed
+    pub fn points(&self, origin: Position) -> Vec<Position> {
+        std::iter::once(origin).chain(self.waypoints.iter().map(|w| origin + *w)).collect()
+    }
+
+    // Where the platform is after `delta` seconds of travel from `position`
+    pub fn advance(&mut self, position: Position, delta: f32) -> Position {
+        let origin = *self.origin.get_or_insert(position);
+        if self.waypoints.is_empty() {
+            return position;
+        }
+        if self.wait > 0. {
+            self.wait -= delta;
+            return position;
+        }
+        let points = self.points(origin);
+        // the path may have been shortened since the target was picked
+        self.target = self.target.min(points.len() - 1);
+        let target = points[self.target];
+        let to = target - position;
+        let distance = to.length();
+        let travel = self.speed * delta;
+        if distance > travel {
+            return position + to * (travel / distance);
+        }
+        self.wait = self.pause;
+        self.target = self.next_target(points.len());
+        target
+    }
+
+    fn next_target(&mut self, count: usize) -> usize {
+        if self.looping {
+            (self.target + 1) % count
+        } else if (self.reverse && self.target == 0) || (!self.reverse && self.target + 1 == count) {
+            self.reverse = !self.reverse;
+            self.next_target(count)
+        } else if self.reverse {
+            self.target - 1
+        } else {
+            self.target + 1
         }
     }
 }
 
+// A ramp in the bottom of its tile. The surface runs straight between the heights at the
+// left and right edges, measured up from the bottom of the tile.
+#[derive(Component, Debug, Default, Clone, Copy, Serialize, Deserialize)]
+#[storage(VecStorage)]
+pub struct Slope {
+    pub left: f32,
+    pub right: f32
+}
+
+impl Slope {
+    pub fn for_tool(tool: ToolPalette) -> Option<Slope> {
+        match tool {
+            ToolPalette::SlopeUp => Some(Slope { left: 0., right: 32. }),
+            ToolPalette::SlopeDown => Some(Slope { left: 32., right: 0. }),
+            ToolPalette::HalfSlopeUp => Some(Slope { left: 0., right: 16. }),
+            ToolPalette::HalfSlopeDown => Some(Slope { left: 16., right: 0. }),
+            _ => None
+        }
+    }
+
+    // The part of the tile the ramp sits in, from its highest point down
+    pub fn solid(&self, tile: &Hitbox) -> Hitbox {
+        let height = self.left.max(self.right);
+        Hitbox {
+            position: Position::new(tile.position.x, tile.position.y + tile.height - height),
+            width: tile.width,
+            height
+        }
+    }
+
+    // Height of the surface above `x`, for a box with the same bottom and width as the tile
+    pub fn surface_y(&self, tile: &Hitbox, x: f32) -> f32 {
+        let t = ((x - tile.position.x) / tile.width).clamp(0., 1.);
+        tile.position.y + tile.height - (self.left + (self.right - self.left) * t)
+    }
+
+    // Unit normal pointing out of the surface
+    pub fn normal(&self, tile: &Hitbox) -> Position {
+        let normal = Position::new(self.left - self.right, -tile.width);
+        normal / normal.length()
+    }
+
+    // Only the underside and the tall end are flat, anything else meets the surface
+    pub fn blocks(&self, normal: Position) -> bool {
+        normal.y > 0. || (normal.x > 0. && self.right > self.left) || (normal.x < 0. && self.left > self.right)
+    }
+}
 
-#[derive(Component, Debug, Default, Clone, Copy, Deserialize, Serialize, PartialEq)]
+// What a tile is like to stand on. Tiles without one are plain ground.
+#[derive(Component, Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
 #[storage(VecStorage)]
+pub struct Surface {
+    // scales how quickly speed is gained and lost on it
+    pub friction: f32,
+    // scales the top running speed
+    pub speed: f32,
+    // carries whatever stands on it sideways, in pixels per second
+    pub conveyor: f32,
+    // fraction of the landing speed bounced back up
+    pub bounce: f32
+}
+
+impl Default for Surface {
+    fn default() -> Self {
+        Surface {
+            friction: 1.,
+            speed: 1.,
+            conveyor: 0.,
+            bounce: 0.
+        }
+    }
+}
+
+impl Surface {
+    pub fn for_tool(tool: ToolPalette) -> Option<Surface> {
+        let ground = Surface::default();
+        match tool {
+            ToolPalette::Ice => Some(Surface { friction: 0.1, ..ground }),
+            ToolPalette::Mud => Some(Surface { friction: 2., speed: 0.4, ..ground }),
+            ToolPalette::Conveyor => Some(Surface { conveyor: 100., ..ground }),
+            ToolPalette::BouncePad => Some(Surface { bounce: 1., ..ground }),
+            _ => None
+        }
+    }
+}
+
+// Only solid to things landing on it from above
+#[derive(Component, Debug, Default, Clone, Copy, Serialize, Deserialize)]
+#[storage(NullStorage)]
+pub struct OneWay;
+
+
+#[derive(Debug, Default, Clone, Copy, Deserialize, Serialize, PartialEq)]
 pub struct Hitbox {
     pub width: f32,
     pub height: f32,
     pub position: Position
 }
 
+// Flagged so the spatial grid can follow inserts, moves and removals
+impl Component for Hitbox {
+    type Storage = FlaggedStorage<Self, VecStorage<Self>>;
+}
+
 impl Hitbox {
     pub fn new(x: f32, y: f32) -> Self {
         Hitbox {
@@ -263,15 +687,80 @@ impl Hitbox {
             height: 32.
         }
     }
+
+    // Moves along `motion` towards a static `other`, returning the fraction of the motion
+    // travelled before touching it and the normal of the face that was hit
+    pub fn sweep(&self, motion: Position, other: &Hitbox) -> Option<Sweep> {
+        let (entry_x, exit_x) = sweep_axis(self.position.x, self.width, other.position.x, other.width, motion.x)?;
+        let (entry_y, exit_y) = sweep_axis(self.position.y, self.height, other.position.y, other.height, motion.y)?;
+        let entry = entry_x.max(entry_y);
+        let exit = exit_x.min(exit_y);
+        // already overlapping, moving apart or not reaching it this step
+        if entry > exit || !(0. ..=1.).contains(&entry) {
+            return None;
+        }
+        let normal = if entry_x > entry_y {
+            Position::new(-motion.x.signum(), 0.)
+        } else {
+            Position::new(0., -motion.y.signum())
+        };
+        Some(Sweep {
+            time: entry,
+            normal
+        })
+    }
+
+    // Snaps flush against the face of `other` given by a sweep normal
+    pub fn flush_against(&mut self, other: &Hitbox, normal: Position) {
+        if normal.x < 0. {
+            self.position.x = other.position.x - self.width;
+        } else if normal.x > 0. {
+            self.position.x = other.position.x + other.width;
+        } else if normal.y < 0. {
+            self.position.y = other.position.y - self.height;
+        } else if normal.y > 0. {
+            self.position.y = other.position.y + other.height;
+        }
+    }
 }
 
-impl Into<Rectangle> for Hitbox {
-    fn into(self) -> Rectangle {
+// Gaps smaller than this are treated as touching so float error doesn't snag on seams
+pub const EPSILON: f32 = 0.001;
+
+// Entry and exit times along one axis; None when the boxes can't meet on it
+fn sweep_axis(start: f32, size: f32, other_start: f32, other_size: f32, motion: f32) -> Option<(f32, f32)> {
+    let end = start + size;
+    let other_end = other_start + other_size;
+    if motion.abs() < EPSILON {
+        if start < other_end - EPSILON && end > other_start + EPSILON {
+            Some((f32::NEG_INFINITY, f32::INFINITY))
+        } else {
+            None
+        }
+    } else if motion > 0. {
+        let gap = other_start - end;
+        let gap = if gap > -EPSILON { gap.max(0.) } else { gap };
+        Some((gap / motion, (other_end - start) / motion))
+    } else {
+        let gap = start - other_end;
+        let gap = if gap > -EPSILON { gap.max(0.) } else { gap };
+        Some((gap / -motion, (end - other_start) / -motion))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Sweep {
+    pub time: f32,
+    pub normal: Position
+}
+
+impl From<Hitbox> for Rectangle {
+    fn from(r: Hitbox) -> Rectangle {
         Rectangle {
-            x: self.position.x,
-            y: self.position.y,
-            width: self.width,
-            height: self.height
+            x: r.position.x,
+            y: r.position.y,
+            width: r.width,
+            height: r.height
         }
     }
 }
@@ -298,22 +787,29 @@ impl CollisionsRec for Hitbox {
     }
 }
 
-#[derive(Component, Debug, Default, Clone, Copy)]
-#[storage(VecStorage)]
-pub struct Triggerbox {
-    pub width: f32,
-    pub height: f32,
-    pub position: Position
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Contact {
+    pub entity: Entity,
+    pub normal: Position
 }
 
-impl Into<Rectangle> for Triggerbox {
-    fn into(self) -> Rectangle {
-        Rectangle {
-            x: self.position.x,
-            y: self.position.y,
-            width: self.width,
-            height: self.height
-        }
+// Everything a moveable touched during the last simulation step
+#[derive(Component, Debug, Default, Clone)]
+#[storage(VecStorage)]
+pub struct Contacts(pub Vec<Contact>);
+
+impl Contacts {
+    pub fn on_ground(&self) -> bool {
+        self.0.iter().any(|c| c.normal.y < 0.)
+    }
+
+    // slopes push sideways too, only flat faces count as walls
+    pub fn wall_left(&self) -> bool {
+        self.0.iter().any(|c| c.normal.x > 0. && c.normal.y == 0.)
+    }
+
+    pub fn wall_right(&self) -> bool {
+        self.0.iter().any(|c| c.normal.x < 0. && c.normal.y == 0.)
     }
 }
 
@@ -325,29 +821,48 @@ pub struct Rect {
     pub position: Position
 }
 
-impl Into<Rectangle> for Rect {
-    fn into(self) -> Rectangle {
+impl From<Rect> for Rectangle {
+    fn from(r: Rect) -> Rectangle {
         Rectangle {
-            x: self.position.x,
-            y: self.position.y,
-            width: self.width,
-            height: self.height
+            x: r.position.x,
+            y: r.position.y,
+            width: r.width,
+            height: r.height
         }
     }
 }
 
-#[derive(Component, Debug, Default, Clone, Copy)]
+// Marks what the editor can select and move; the drag itself lives in Selection
+#[derive(Component, Debug, Default, Clone, Copy, Serialize, Deserialize)]
 #[storage(VecStorage)]
-pub struct DragBox {
-    pub dragging: bool,
-    pub drag_offset: Position,
+pub struct DragBox {}
+
+// A selection being moved: where the mouse went down and where everything started
+#[derive(Debug)]
+pub struct SelectionDrag {
+    pub origin: Position,
+    // top-left of the selection, the point that gets snapped to the grid
+    pub anchor: Position,
+    pub starts: Vec<(Entity, Position)>
 }
 
-impl DragBox {
-    pub fn default() -> Self {
-        DragBox {
-            dragging: false,
-            drag_offset: Position::zero()
+#[derive(Default)]
+pub struct Selection {
+    pub entities: Vec<Entity>,
+    // corner where a marquee drag started
+    pub marquee: Option<Position>,
+    pub drag: Option<SelectionDrag>,
+    pub clipboard: Vec<SavedEntity>
+}
+
+impl Selection {
+    pub fn contains(&self, entity: Entity) -> bool {
+        self.entities.contains(&entity)
+    }
+
+    pub fn add(&mut self, entity: Entity) {
+        if !self.contains(entity) {
+            self.entities.push(entity);
         }
     }
 }
@@ -369,18 +884,25 @@ pub trait CollisionsRec {
 
 use strum_macros::EnumIter; // etc.
 
-#[derive(AsStaticStr, EnumIter,  Debug, Clone, Copy, PartialEq)]
+#[derive(AsStaticStr, EnumIter,  Debug, Default, Clone, Copy, PartialEq)]
 pub enum ToolPalette {
+    #[default]
     Block,
     FallingBlock,
-    SpikeBlock
+    SpikeBlock,
+    OneWay,
+    MovingPlatform,
+    SlopeUp,
+    SlopeDown,
+    HalfSlopeUp,
+    HalfSlopeDown,
+    Ice,
+    Mud,
+    Conveyor,
+    BouncePad,
+    SpawnPoint
 }
 
-impl Default for ToolPalette {
-    fn default() -> Self {
-        ToolPalette::Block
-    }
-}
 
 
 #[derive(Component, Debug, Default, Clone)]
@@ -428,16 +950,298 @@ impl Icon {
     }
 }
 
+// Icons are drawn at twice their 16px size
+impl CollisionsPoint<Position> for Icon {
+    fn point_inside_rec(&self, point: Position) -> bool {
+        Hitbox::new(self.position.x, self.position.y).point_inside_rec(point)
+    }
+}
+
 pub struct EditState {
     pub should_save: bool,
-    pub editting: bool
+    // clear the level and read CurrentLevel back in
+    pub should_load: bool,
+    pub should_toggle: bool,
+    // put the level back to the snapshot taken when play started
+    pub should_reset: bool,
+    pub editting: bool,
+    // a palette tool is picked, so left clicks place rather than select
+    pub placing: bool,
+    // the bin tool: left clicks delete instead of placing
+    pub erasing: bool
+}
+
+impl Default for EditState {
+    fn default() -> Self {
+        EditState::new()
+    }
 }
 
 impl EditState {
     pub fn new() -> EditState {
         EditState {
             should_save: false,
+            should_load: false,
+            should_toggle: false,
+            should_reset: false,
             editting: true,
+            placing: false,
+            erasing: false,
+        }
+    }
+}
+
+// The authored level, serialized when play mode starts
+#[derive(Debug, Default)]
+pub struct LevelSnapshot(pub Option<String>);
+
+const MESSAGE_TIME: f32 = 4.;
+
+// A line of feedback for the player, like a failed save, shown along the bottom of the screen
+#[derive(Debug, Default)]
+pub struct StatusMessage {
+    pub text: Option<String>,
+    time_left: f32
+}
+
+impl StatusMessage {
+    pub fn show(&mut self, text: String) {
+        self.text = Some(text);
+        self.time_left = MESSAGE_TIME;
+    }
+
+    pub fn tick(&mut self, delta: f32) {
+        self.time_left -= delta;
+        if self.time_left <= 0. {
+            self.text = None;
         }
     }
-}
\ No newline at end of file
+}
+
+// Long stalls (window drags, breakpoints) would otherwise queue up hundreds of steps
+const MAX_FRAME_TIME: f32 = 0.25;
+
+pub struct Time {
+    pub fixed_delta: f32,
+    pub accumulator: f32,
+    // how far between the last two simulation steps the current frame is, 0..1
+    pub alpha: f32,
+    // length of the last drawn frame, for things that move per frame rather than per step
+    pub frame_delta: f32
+}
+
+impl Time {
+    pub fn new(fixed_delta: f32) -> Time {
+        Time {
+            fixed_delta,
+            accumulator: 0.,
+            alpha: 0.,
+            frame_delta: 0.
+        }
+    }
+
+    pub fn advance(&mut self, frame_time: f32) {
+        self.frame_delta = frame_time.min(MAX_FRAME_TIME);
+        self.accumulator += self.frame_delta;
+    }
+
+    // Returns true while there is enough time banked for another simulation step
+    pub fn step(&mut self) -> bool {
+        if self.accumulator >= self.fixed_delta {
+            self.accumulator -= self.fixed_delta;
+            true
+        } else {
+            self.alpha = self.accumulator / self.fixed_delta;
+            false
+        }
+    }
+}
+// Keys the game reads; polled once a frame so systems never touch the raylib handle
+const TRACKED_KEYS: [KeyboardKey; 19] = [
+    KEY_LEFT, KEY_RIGHT, KEY_UP, KEY_DOWN, KEY_SPACE, KEY_P, KEY_BACKSPACE, KEY_ENTER,
+    KEY_LEFT_CONTROL, KEY_RIGHT_CONTROL, KEY_LEFT_SHIFT, KEY_RIGHT_SHIFT, KEY_Z, KEY_Y, KEY_C, KEY_X, KEY_V,
+    KEY_W, KEY_L,
+];
+// Keys that produce a character when typing a level name
+const TEXT_KEYS: [(KeyboardKey, char); 37] = [
+    (KEY_A, 'a'), (KEY_B, 'b'), (KEY_C, 'c'), (KEY_D, 'd'), (KEY_E, 'e'), (KEY_F, 'f'), (KEY_G, 'g'),
+    (KEY_H, 'h'), (KEY_I, 'i'), (KEY_J, 'j'), (KEY_K, 'k'), (KEY_L, 'l'), (KEY_M, 'm'), (KEY_N, 'n'),
+    (KEY_O, 'o'), (KEY_P, 'p'), (KEY_Q, 'q'), (KEY_R, 'r'), (KEY_S, 's'), (KEY_T, 't'), (KEY_U, 'u'),
+    (KEY_V, 'v'), (KEY_W, 'w'), (KEY_X, 'x'), (KEY_Y, 'y'), (KEY_Z, 'z'),
+    (KEY_ZERO, '0'), (KEY_ONE, '1'), (KEY_TWO, '2'), (KEY_THREE, '3'), (KEY_FOUR, '4'),
+    (KEY_FIVE, '5'), (KEY_SIX, '6'), (KEY_SEVEN, '7'), (KEY_EIGHT, '8'), (KEY_NINE, '9'),
+    (KEY_MINUS, '-'),
+];
+const TRACKED_BUTTONS: [MouseButton; 3] = [MOUSE_LEFT_BUTTON, MOUSE_RIGHT_BUTTON, MOUSE_MIDDLE_BUTTON];
+
+#[derive(Debug, Default, Clone)]
+pub struct InputState {
+    pub keys_down: Vec<KeyboardKey>,
+    pub keys_pressed: Vec<KeyboardKey>,
+    pub keys_released: Vec<KeyboardKey>,
+    // on screen, for UI; editor picking uses `world_mouse`
+    pub mouse_position: Position,
+    // the mouse in world coordinates, worked out by CameraControl each frame
+    pub world_mouse: Position,
+    pub wheel: f32,
+    pub mouse_down: Vec<MouseButton>,
+    pub mouse_pressed: Vec<MouseButton>,
+    pub mouse_released: Vec<MouseButton>,
+    // characters typed this frame, in TEXT_KEYS order
+    pub typed: Vec<char>
+}
+
+impl InputState {
+    pub fn poll(&mut self, rl: &RaylibHandle) {
+        self.keys_down = TRACKED_KEYS.iter().copied().filter(|k| rl.is_key_down(*k)).collect();
+        self.keys_pressed = TRACKED_KEYS.iter().copied().filter(|k| rl.is_key_pressed(*k)).collect();
+        self.keys_released = TRACKED_KEYS.iter().copied().filter(|k| rl.is_key_released(*k)).collect();
+        self.mouse_position = Position::from(rl.get_mouse_position());
+        self.wheel = rl.get_mouse_wheel_move() as f32;
+        self.mouse_down = TRACKED_BUTTONS.iter().copied().filter(|b| rl.is_mouse_button_down(*b)).collect();
+        self.mouse_pressed = TRACKED_BUTTONS.iter().copied().filter(|b| rl.is_mouse_button_pressed(*b)).collect();
+        self.mouse_released = TRACKED_BUTTONS.iter().copied().filter(|b| rl.is_mouse_button_released(*b)).collect();
+        self.typed = TEXT_KEYS.iter().filter(|(k, _)| rl.is_key_pressed(*k)).map(|(_, c)| *c).collect();
+    }
+
+    // Used when there is no window: pressed/released are worked out from the previous frame
+    pub fn set_keys(&mut self, keys: Vec<KeyboardKey>) {
+        self.keys_pressed = keys.iter().copied().filter(|k| !self.keys_down.contains(k)).collect();
+        self.keys_released = self.keys_down.iter().copied().filter(|k| !keys.contains(k)).collect();
+        self.keys_down = keys;
+    }
+
+    pub fn is_key_down(&self, key: KeyboardKey) -> bool {
+        self.keys_down.contains(&key)
+    }
+
+    pub fn is_key_pressed(&self, key: KeyboardKey) -> bool {
+        self.keys_pressed.contains(&key)
+    }
+
+    pub fn is_key_released(&self, key: KeyboardKey) -> bool {
+        self.keys_released.contains(&key)
+    }
+
+    pub fn ctrl_down(&self) -> bool {
+        self.is_key_down(KEY_LEFT_CONTROL) || self.is_key_down(KEY_RIGHT_CONTROL)
+    }
+
+    pub fn shift_down(&self) -> bool {
+        self.is_key_down(KEY_LEFT_SHIFT) || self.is_key_down(KEY_RIGHT_SHIFT)
+    }
+
+    pub fn is_mouse_button_down(&self, button: MouseButton) -> bool {
+        self.mouse_down.contains(&button)
+    }
+
+    pub fn is_mouse_button_pressed(&self, button: MouseButton) -> bool {
+        self.mouse_pressed.contains(&button)
+    }
+
+    pub fn is_mouse_button_released(&self, button: MouseButton) -> bool {
+        self.mouse_released.contains(&button)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sweep_axis_stationary() {
+        assert_eq!(sweep_axis(0., 32., 16., 32., 0.), Some((f32::NEG_INFINITY, f32::INFINITY)));
+        // only touching edges don't count as overlapping
+        assert_eq!(sweep_axis(0., 32., 32., 32., 0.), None);
+    }
+
+    #[test]
+    fn sweep_axis_moving() {
+        assert_eq!(sweep_axis(0., 32., 64., 32., 64.), Some((0.5, 1.5)));
+        assert_eq!(sweep_axis(64., 32., 0., 32., -64.), Some((0.5, 1.5)));
+        // sinking in by less than EPSILON is treated as touching rather than overlapping
+        assert_eq!(sweep_axis(0., 32., 31.9995, 32., 10.).unwrap().0, 0.);
+    }
+
+    #[test]
+    fn sweep_hits_side() {
+        let mover = Hitbox::new(0., 0.);
+        let wall = Hitbox::new(64., 0.);
+        let hit = mover.sweep(Position::new(64., 0.), &wall).unwrap();
+        assert_eq!(hit, Sweep { time: 0.5, normal: Position::new(-1., 0.) });
+    }
+
+    #[test]
+    fn sweep_lands_on_top() {
+        let mover = Hitbox::new(8., 0.);
+        let floor = Hitbox::new(0., 40.);
+        let hit = mover.sweep(Position::new(4., 16.), &floor).unwrap();
+        assert_eq!(hit, Sweep { time: 0.5, normal: Position::new(0., -1.) });
+    }
+
+    #[test]
+    fn sweep_misses() {
+        let mover = Hitbox::new(0., 0.);
+        // too short, moving away and passing beside
+        assert_eq!(mover.sweep(Position::new(16., 0.), &Hitbox::new(64., 0.)), None);
+        assert_eq!(mover.sweep(Position::new(-16., 0.), &Hitbox::new(64., 0.)), None);
+        assert_eq!(mover.sweep(Position::new(64., 0.), &Hitbox::new(64., 32.)), None);
+    }
+
+    #[test]
+    fn flush_against_faces() {
+        let wall = Hitbox::new(64., 64.);
+        let mut mover = Hitbox::new(40., 64.);
+        mover.flush_against(&wall, Position::new(-1., 0.));
+        assert_eq!(mover.position, Position::new(32., 64.));
+        mover.flush_against(&wall, Position::new(0., -1.));
+        assert_eq!(mover.position, Position::new(32., 32.));
+    }
+
+    fn platform(looping: bool) -> MovingPlatform {
+        MovingPlatform {
+            waypoints: vec![Position::new(64., 0.), Position::new(64., 64.)],
+            looping,
+            speed: 64.,
+            pause: 0.,
+            ..MovingPlatform::default()
+        }
+    }
+
+    #[test]
+    fn advance_moves_at_speed() {
+        let mut p = platform(false);
+        let start = Position::new(0., 0.);
+        // the first call takes the origin and heads for it
+        assert_eq!(p.advance(start, 0.5), start);
+        assert_eq!(p.target, 1);
+        let position = p.advance(start, 0.5);
+        assert_eq!(position, Position::new(32., 0.));
+        assert_eq!(p.advance(position, 0.5), Position::new(64., 0.));
+        assert_eq!(p.target, 2);
+    }
+
+    #[test]
+    fn advance_waits_at_waypoints() {
+        let mut p = MovingPlatform { pause: 1., ..platform(false) };
+        let start = Position::new(0., 0.);
+        p.advance(start, 0.1);
+        assert_eq!(p.advance(start, 0.5), start);
+        assert_eq!(p.advance(start, 0.5), start);
+        assert_ne!(p.advance(start, 0.5), start);
+    }
+
+    #[test]
+    fn next_target_turns_back() {
+        let mut p = platform(false);
+        let targets: Vec<usize> = (0..5).map(|_| { p.target = p.next_target(3); p.target }).collect();
+        assert_eq!(targets, vec![1, 2, 1, 0, 1]);
+    }
+
+    #[test]
+    fn next_target_loops() {
+        let mut p = platform(true);
+        let targets: Vec<usize> = (0..5).map(|_| { p.target = p.next_target(3); p.target }).collect();
+        assert_eq!(targets, vec![1, 2, 0, 1, 2]);
+    }
+}
@@ -0,0 +1,197 @@
+use raylib::consts::KeyboardKey::{self, *};
+use serde::Deserialize;
+use specs::prelude::*;
+
+use crate::components::{EditState, Hitbox, InputState, Moveable, PlatformController, StatusMessage, Time};
+use crate::level::CurrentLevel;
+
+// One entry of a headless script: hold `keys` down for `frames` simulation steps
+#[derive(Debug, Deserialize)]
+pub struct ScriptStep {
+    pub frames: u32,
+    #[serde(default)]
+    pub keys: Vec<String>,
+    // checked once the step's frames have run, a mismatch fails the script
+    #[serde(default)]
+    pub expect: Option<Expect>,
+    // checked after every frame of the step
+    #[serde(default)]
+    pub during: Option<Expect>
+}
+
+// What the player should be doing; fields left out aren't checked
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct Expect {
+    pub grounded: Option<bool>,
+    pub rising: Option<bool>,
+    pub dead: Option<bool>,
+    // inclusive ranges for the hitbox's top left corner and the horizontal velocity
+    pub x: Option<(f32, f32)>,
+    pub y: Option<(f32, f32)>,
+    pub speed: Option<(f32, f32)>
+}
+
+fn key_from_name(name: &str) -> Option<KeyboardKey> {
+    match name.to_lowercase().as_str() {
+        "left" => Some(KEY_LEFT),
+        "right" => Some(KEY_RIGHT),
+        "up" => Some(KEY_UP),
+        "down" => Some(KEY_DOWN),
+        "space" | "jump" => Some(KEY_SPACE),
+        "x" | "dash" => Some(KEY_X),
+        _ => None
+    }
+}
+
+// Loads the level (the default one unless a path is given) and steps the simulation through a script without opening a window.
+// The player's state is printed after every step so runs can be diffed in CI.
+pub fn run(script_path: &str, level_path: Option<&str>) -> Result<(), String> {
+    let contents = std::fs::read_to_string(script_path)
+        .map_err(|e| format!("Could not read script {}: {}", script_path, e))?;
+    let script: Vec<ScriptStep> = ron::de::from_str(&contents)
+        .map_err(|e| format!("Could not parse script {}: {}", script_path, e))?;
+
+    let mut world = World::new();
+    crate::setup_world(&mut world);
+    let mut simulation = crate::simulation();
+    simulation.setup(&mut world);
+    if let Some(path) = level_path {
+        world.insert(CurrentLevel(path.into()));
+    }
+    crate::systems::Deserialize.run_now(&world);
+    world.maintain();
+    if let Some(text) = &world.read_resource::<StatusMessage>().text {
+        return Err(text.clone());
+    }
+    // the editor would have grown the bounds around the level before play
+    crate::systems::FitLevel.run_now(&world);
+    // start play the same way pressing P does, so the player is put on the spawn point
+    world.write_resource::<EditState>().should_toggle = true;
+    crate::should_toggle(&mut world);
+
+    let mut frame = 0;
+    for step in &script {
+        let keys = step.keys
+            .iter()
+            .map(|name| key_from_name(name).ok_or_else(|| format!("Unknown key '{}' in script", name)))
+            .collect::<Result<Vec<_>, _>>()?;
+        for _ in 0..step.frames {
+            world.write_resource::<InputState>().set_keys(keys.clone());
+            world.write_resource::<Time>().advance(crate::FIXED_DELTA);
+            crate::run_simulation(&mut world, &mut simulation);
+            frame += 1;
+            if let Some(during) = &step.during {
+                check(&world, frame, during)?;
+            }
+        }
+        report(&world, frame);
+        if let Some(expect) = &step.expect {
+            check(&world, frame, expect)?;
+        }
+    }
+    Ok(())
+}
+
+fn check(world: &World, frame: u32, expect: &Expect) -> Result<(), String> {
+    let controllers = world.read_storage::<PlatformController>();
+    let hitboxes = world.read_storage::<Hitbox>();
+    let moveables = world.read_storage::<Moveable>();
+    let (controller, hitbox, m) = (&controllers, &hitboxes, &moveables)
+        .join()
+        .next()
+        .ok_or_else(|| format!("frame {}: there is no player to check", frame))?;
+    if let Some(grounded) = expect.grounded {
+        if controller.can_jump != grounded {
+            return Err(format!("frame {}: expected grounded {}, was {}", frame, grounded, controller.can_jump));
+        }
+    }
+    // up is negative y
+    if let Some(rising) = expect.rising {
+        if (m.velocity.y < 0.) != rising {
+            return Err(format!("frame {}: expected rising {}, velocity was {:.2}", frame, rising, m.velocity.y));
+        }
+    }
+    if let Some(dead) = expect.dead {
+        if controller.is_dead() != dead {
+            return Err(format!("frame {}: expected dead {}, was {}", frame, dead, controller.is_dead()));
+        }
+    }
+    within(frame, "x", hitbox.position.x, expect.x)?;
+    within(frame, "y", hitbox.position.y, expect.y)?;
+    within(frame, "speed", m.velocity.x, expect.speed)
+}
+
+fn within(frame: u32, what: &str, value: f32, range: Option<(f32, f32)>) -> Result<(), String> {
+    match range {
+        Some((min, max)) if value < min || value > max => Err(format!("frame {}: expected {} between {} and {}, was {:.2}", frame, what, min, max, value)),
+        _ => Ok(())
+    }
+}
+
+fn report(world: &World, frame: u32) {
+    let controllers = world.read_storage::<PlatformController>();
+    let hitboxes = world.read_storage::<Hitbox>();
+    let moveables = world.read_storage::<Moveable>();
+    for (controller, hitbox, m) in (&controllers, &hitboxes, &moveables).join() {
+        println!(
+            "frame {}: position ({:.2}, {:.2}) velocity ({:.2}, {:.2}) grounded {}",
+            frame, hitbox.position.x, hitbox.position.y, m.velocity.x, m.velocity.y, controller.can_jump
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    // Runs tests/scripts/<script>.ron against tests/fixtures/levels/<level>.ron
+    fn run(script: &str, level: &str) {
+        let root = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests");
+        let script = root.join("scripts").join(format!("{}.ron", script));
+        let level = root.join("fixtures/levels").join(format!("{}.ron", level));
+        if let Err(e) = super::run(script.to_str().unwrap(), level.to_str()) {
+            panic!("{}: {}", script.display(), e);
+        }
+    }
+
+    #[test]
+    fn jump_script() {
+        run("jump", "flat");
+    }
+
+    #[test]
+    fn jump_control_script() {
+        run("jump_control", "flat");
+    }
+
+    #[test]
+    fn wall_jump_script() {
+        run("wall_jump", "wall");
+    }
+
+    #[test]
+    fn dash_script() {
+        run("dash", "flat");
+    }
+
+    #[test]
+    fn oneway_script() {
+        run("oneway", "oneway");
+    }
+
+    #[test]
+    fn falling_script() {
+        run("falling", "falling");
+    }
+
+    #[test]
+    fn surfaces_script() {
+        run("surfaces", "surfaces");
+    }
+
+    #[test]
+    fn slopes_script() {
+        run("slopes", "slopes");
+    }
+}
@@ -0,0 +1,167 @@
+use specs::saveload::{EntityData, SimpleMarker};
+
+use crate::components::Position;
+use crate::level::Components;
+use crate::systems::NetworkSync;
+
+pub const HISTORY_DEPTH: usize = 100;
+
+// A marked entity with everything that gets saved for it, so a removal can be undone
+pub type SavedEntity = EntityData<SimpleMarker<NetworkSync>, Components>;
+
+// What an editor system did this frame, by marker id. The components are read
+// by HistoryHandler before the world is maintained, deleted entities included.
+pub enum Change {
+    Created(u64),
+    Removed(u64),
+    Moved { marker: u64, from: Position, to: Position },
+    // components changed in place, holding how they were before
+    Edited(SavedEntity)
+}
+
+pub enum Edit {
+    Create(SavedEntity),
+    Remove(SavedEntity),
+    Move { marker: u64, from: Position, to: Position },
+    Replace { before: Box<SavedEntity>, after: Box<SavedEntity> }
+}
+
+// Everything changed in one frame, undone and redone together
+pub struct Command(pub Vec<Edit>);
+
+pub struct EditHistory {
+    pub depth: usize,
+    pub pending: Vec<Change>,
+    undo: Vec<Command>,
+    redo: Vec<Command>,
+    // edits made since a mouse button went down, pushed as one command when it comes back up
+    stroke: Vec<Edit>
+}
+
+impl EditHistory {
+    pub fn new(depth: usize) -> Self {
+        EditHistory {
+            depth,
+            pending: Vec::new(),
+            undo: Vec::new(),
+            redo: Vec::new(),
+            stroke: Vec::new()
+        }
+    }
+
+    pub fn record(&mut self, change: Change) {
+        self.pending.push(change);
+    }
+
+    // A new edit makes the redo stack meaningless
+    pub fn push(&mut self, command: Command) {
+        self.redo.clear();
+        self.undo.push(command);
+        if self.undo.len() > self.depth {
+            let excess = self.undo.len() - self.depth;
+            self.undo.drain(..excess);
+        }
+    }
+
+    // Adds a frame's edits to the current stroke, which ends once no button is `held`,
+    // so a painted or erased line is undone in one go
+    pub fn add(&mut self, edits: Vec<Edit>, held: bool) {
+        self.stroke.extend(edits);
+        if !held {
+            self.end_stroke();
+        }
+    }
+
+    pub fn end_stroke(&mut self) {
+        if !self.stroke.is_empty() {
+            let edits = std::mem::take(&mut self.stroke);
+            self.push(Command(edits));
+        }
+    }
+
+    pub fn take_undo(&mut self) -> Option<Command> {
+        self.undo.pop()
+    }
+
+    pub fn take_redo(&mut self) -> Option<Command> {
+        self.redo.pop()
+    }
+
+    pub fn undone(&mut self, command: Command) {
+        self.redo.push(command);
+    }
+
+    pub fn redone(&mut self, command: Command) {
+        self.undo.push(command);
+    }
+
+    pub fn clear(&mut self) {
+        self.pending.clear();
+        self.stroke.clear();
+        self.undo.clear();
+        self.redo.clear();
+    }
+}
+
+impl Default for EditHistory {
+    fn default() -> Self {
+        EditHistory::new(HISTORY_DEPTH)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn moved(marker: u64) -> Command {
+        Command(vec![Edit::Move { marker, from: Position::zero(), to: Position::new(32., 0.) }])
+    }
+
+    fn marker_of(command: &Command) -> u64 {
+        match command.0[0] {
+            Edit::Move { marker, .. } => marker,
+            _ => panic!("expected a move")
+        }
+    }
+
+    #[test]
+    fn push_trims_to_depth() {
+        let mut history = EditHistory::new(3);
+        for marker in 0..5 {
+            history.push(moved(marker));
+        }
+        // only the newest three are kept, oldest dropped first
+        let undone: Vec<u64> = std::iter::from_fn(|| history.take_undo()).map(|c| marker_of(&c)).collect();
+        assert_eq!(undone, vec![4, 3, 2]);
+    }
+
+    #[test]
+    fn push_clears_redo() {
+        let mut history = EditHistory::new(3);
+        history.push(moved(0));
+        let command = history.take_undo().unwrap();
+        history.undone(command);
+        history.push(moved(1));
+        assert!(history.take_redo().is_none());
+    }
+
+    #[test]
+    fn stroke_is_one_command() {
+        let mut history = EditHistory::new(3);
+        history.add(vec![Edit::Move { marker: 0, from: Position::zero(), to: Position::zero() }], true);
+        history.add(Vec::new(), true);
+        history.add(vec![Edit::Move { marker: 1, from: Position::zero(), to: Position::zero() }], true);
+        // nothing is undoable until the button comes back up
+        assert!(history.take_undo().is_none());
+        history.add(Vec::new(), false);
+        assert_eq!(history.take_undo().unwrap().0.len(), 2);
+        assert!(history.take_undo().is_none());
+    }
+
+    #[test]
+    fn clicks_without_edits_push_nothing() {
+        let mut history = EditHistory::new(3);
+        history.add(Vec::new(), false);
+        assert!(history.take_undo().is_none());
+    }
+}
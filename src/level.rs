@@ -0,0 +1,844 @@
+use raylib::consts::rIconDescription;
+use serde::{Deserialize, Serialize};
+use specs::saveload::{EntityData, SimpleMarker};
+use std::{fmt, fs, io, path::{Path, PathBuf}};
+
+use crate::components::{CollisionsPoint, DragBox, FallingBlock, Hazard, Hitbox, MovingPlatform, OneWay, Position, Slope, SpawnPoint, Sprite, Surface};
+use crate::systems::NetworkSync;
+
+// Bump this and add a migration below whenever the saved components change
+pub const LEVEL_VERSION: u32 = 7;
+pub const LEVEL_DIR: &str = "levels";
+// where the only level was kept before LEVEL_DIR existed
+pub const LEGACY_LEVEL: &str = "storage.ron";
+// how far below the bottom of a level things fall before they are removed
+pub const KILL_PLANE: f32 = 128.;
+
+#[derive(Debug)]
+pub enum LevelError {
+    Io(io::Error),
+    Parse(ron::de::Error),
+    Write(ron::ser::Error),
+    Version(u32)
+}
+
+impl fmt::Display for LevelError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            LevelError::Io(ref e) => write!(f, "{}", e),
+            LevelError::Parse(ref e) => write!(f, "could not parse level: {}", e),
+            LevelError::Write(ref e) => write!(f, "could not write level: {}", e),
+            LevelError::Version(v) => write!(f, "level version {} is newer than this build supports ({})", v, LEVEL_VERSION)
+        }
+    }
+}
+
+impl From<io::Error> for LevelError {
+    fn from(e: io::Error) -> Self {
+        LevelError::Io(e)
+    }
+}
+
+impl From<ron::de::Error> for LevelError {
+    fn from(e: ron::de::Error) -> Self {
+        LevelError::Parse(e)
+    }
+}
+
+impl From<ron::ser::Error> for LevelError {
+    fn from(e: ron::ser::Error) -> Self {
+        LevelError::Write(e)
+    }
+}
+
+// Everything about a level that isn't an entity; kept as a resource so saving writes it back out
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LevelInfo {
+    pub name: String,
+    pub author: String,
+    // top left corner, past the origin when tiles were placed left of or above it
+    pub left: f32,
+    pub top: f32,
+    pub width: f32,
+    pub height: f32,
+    pub kill_plane: f32
+}
+
+impl Default for LevelInfo {
+    fn default() -> Self {
+        LevelInfo {
+            name: "untitled".to_string(),
+            author: String::new(),
+            left: 0.,
+            top: 0.,
+            width: crate::WIDTH as f32,
+            height: crate::HEIGHT as f32,
+            kill_plane: KILL_PLANE
+        }
+    }
+}
+
+impl LevelInfo {
+    pub fn bounds(&self) -> Hitbox {
+        Hitbox {
+            position: Position::new(self.left, self.top),
+            width: self.width,
+            height: self.height
+        }
+    }
+
+    pub fn kill_y(&self) -> f32 {
+        self.top + self.height + self.kill_plane
+    }
+
+    // Grows the level to take in `area`, so whatever is placed in the editor can be reached in play
+    pub fn fit(&mut self, area: &Hitbox) {
+        let right = (self.left + self.width).max(area.position.x + area.width);
+        let bottom = (self.top + self.height).max(area.position.y + area.height);
+        self.left = self.left.min(area.position.x);
+        self.top = self.top.min(area.position.y);
+        self.width = right - self.left;
+        self.height = bottom - self.top;
+    }
+}
+
+fn default_kill_plane() -> f32 {
+    KILL_PLANE
+}
+
+// The file layout, `entities` is whatever SerializeComponents writes for that version
+#[derive(Serialize, Deserialize)]
+#[serde(rename = "Level")]
+pub struct Level<E> {
+    pub version: u32,
+    pub name: String,
+    pub author: String,
+    // added without a version bump like kill_plane, older files start at the origin
+    #[serde(default)]
+    pub left: f32,
+    #[serde(default)]
+    pub top: f32,
+    pub width: f32,
+    pub height: f32,
+    // added without a version bump, older files just get the default
+    #[serde(default = "default_kill_plane")]
+    pub kill_plane: f32,
+    pub entities: E
+}
+
+impl<E> Level<E> {
+    pub fn new(info: &LevelInfo, entities: E) -> Self {
+        Level {
+            version: LEVEL_VERSION,
+            name: info.name.clone(),
+            author: info.author.clone(),
+            left: info.left,
+            top: info.top,
+            width: info.width,
+            height: info.height,
+            kill_plane: info.kill_plane,
+            entities
+        }
+    }
+
+    pub fn info(&self) -> LevelInfo {
+        LevelInfo {
+            name: self.name.clone(),
+            author: self.author.clone(),
+            left: self.left,
+            top: self.top,
+            width: self.width,
+            height: self.height,
+            kill_plane: self.kill_plane
+        }
+    }
+}
+
+// Only reads the version so we know which layout to parse the rest as
+#[derive(Deserialize)]
+#[serde(rename = "Level")]
+struct VersionProbe {
+    version: u32
+}
+
+// Up to v3 falling blocks only saved their countdown
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename = "FallingBlock")]
+struct FallingBlockV3 {
+    count: u32,
+    should_fall: bool
+}
+
+// Falling blocks used to save where they were stood on, up to v6
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename = "Triggerbox")]
+struct TriggerboxV6 {
+    width: f32,
+    height: f32,
+    position: Position
+}
+
+// Component tuples as they were saved in each version, in SerializeComponents order.
+// v0 files went through three layouts before the header existed: only what is drawn,
+// then the spawn point, then everything ComponentsV1 has.
+type ComponentsV0 = (
+    Option<Hitbox>,
+    Option<Position>,
+    Option<Sprite>,
+);
+type EntitiesV0 = Vec<EntityData<SimpleMarker<NetworkSync>, ComponentsV0>>;
+type ComponentsV0Spawn = (
+    Option<Hitbox>,
+    Option<Position>,
+    Option<Sprite>,
+    Option<SpawnPoint>,
+);
+type EntitiesV0Spawn = Vec<EntityData<SimpleMarker<NetworkSync>, ComponentsV0Spawn>>;
+type ComponentsV1 = (
+    Option<Hitbox>,
+    Option<Position>,
+    Option<Sprite>,
+    Option<SpawnPoint>,
+    Option<FallingBlockV3>,
+    Option<TriggerboxV6>,
+    Option<DragBox>,
+    Option<Hazard>,
+);
+type EntitiesV1 = Vec<EntityData<SimpleMarker<NetworkSync>, ComponentsV1>>;
+type ComponentsV2 = (
+    Option<Hitbox>,
+    Option<Position>,
+    Option<Sprite>,
+    Option<SpawnPoint>,
+    Option<FallingBlockV3>,
+    Option<TriggerboxV6>,
+    Option<DragBox>,
+    Option<Hazard>,
+    Option<OneWay>,
+);
+type EntitiesV2 = Vec<EntityData<SimpleMarker<NetworkSync>, ComponentsV2>>;
+type ComponentsV3 = (
+    Option<Hitbox>,
+    Option<Position>,
+    Option<Sprite>,
+    Option<SpawnPoint>,
+    Option<FallingBlockV3>,
+    Option<TriggerboxV6>,
+    Option<DragBox>,
+    Option<Hazard>,
+    Option<OneWay>,
+    Option<MovingPlatform>,
+);
+type EntitiesV3 = Vec<EntityData<SimpleMarker<NetworkSync>, ComponentsV3>>;
+type ComponentsV4 = (
+    Option<Hitbox>,
+    Option<Position>,
+    Option<Sprite>,
+    Option<SpawnPoint>,
+    Option<FallingBlock>,
+    Option<TriggerboxV6>,
+    Option<DragBox>,
+    Option<Hazard>,
+    Option<OneWay>,
+    Option<MovingPlatform>,
+);
+type EntitiesV4 = Vec<EntityData<SimpleMarker<NetworkSync>, ComponentsV4>>;
+type ComponentsV5 = (
+    Option<Hitbox>,
+    Option<Position>,
+    Option<Sprite>,
+    Option<SpawnPoint>,
+    Option<FallingBlock>,
+    Option<TriggerboxV6>,
+    Option<DragBox>,
+    Option<Hazard>,
+    Option<OneWay>,
+    Option<MovingPlatform>,
+    Option<Slope>,
+);
+type EntitiesV5 = Vec<EntityData<SimpleMarker<NetworkSync>, ComponentsV5>>;
+type ComponentsV6 = (
+    Option<Hitbox>,
+    Option<Position>,
+    Option<Sprite>,
+    Option<SpawnPoint>,
+    Option<FallingBlock>,
+    Option<TriggerboxV6>,
+    Option<DragBox>,
+    Option<Hazard>,
+    Option<OneWay>,
+    Option<MovingPlatform>,
+    Option<Slope>,
+    Option<Surface>,
+);
+type EntitiesV6 = Vec<EntityData<SimpleMarker<NetworkSync>, ComponentsV6>>;
+type ComponentsV7 = (
+    Option<Hitbox>,
+    Option<Position>,
+    Option<Sprite>,
+    Option<SpawnPoint>,
+    Option<FallingBlock>,
+    Option<DragBox>,
+    Option<Hazard>,
+    Option<OneWay>,
+    Option<MovingPlatform>,
+    Option<Slope>,
+    Option<Surface>,
+);
+type EntitiesV7 = Vec<EntityData<SimpleMarker<NetworkSync>, ComponentsV7>>;
+
+// The layout this build saves
+pub type Components = ComponentsV7;
+
+// The first saves only kept the drawn parts
+fn migrate_v0_spawn(entities: EntitiesV0) -> EntitiesV0Spawn {
+    entities
+        .into_iter()
+        .map(|e| {
+            let (h, p, s) = e.components;
+            EntityData { marker: e.marker, components: (h, p, s, None) }
+        })
+        .collect()
+}
+
+// Until the full layout was saved, falling blocks and spikes came back as plain blocks and
+// nothing could be dragged. Everything placed was draggable and the sprite tells the rest.
+fn migrate_v0_full(entities: EntitiesV0Spawn) -> EntitiesV1 {
+    entities
+        .into_iter()
+        .map(|e| {
+            let (h, p, s, sp) = e.components;
+            let name = s.as_ref().map_or("", |s| s.name.as_str());
+            let fb = if name == "fallingblock" { Some(FallingBlockV3 { count: 0, should_fall: false }) } else { None };
+            let hz = if name == "spikeblock" { Some(Hazard) } else { None };
+            EntityData { marker: e.marker, components: (h, p, s, sp, fb, None, Some(DragBox::default()), hz) }
+        })
+        .collect()
+}
+
+// v0 files were a bare entity list with no header
+fn migrate_v0(entities: EntitiesV1) -> Level<EntitiesV1> {
+    Level::new(&LevelInfo::default(), entities)
+}
+
+// v2 added one-way platforms
+fn migrate_v1(level: Level<EntitiesV1>) -> Level<EntitiesV2> {
+    let info = level.info();
+    let entities = level.entities
+        .into_iter()
+        .map(|e| {
+            let (h, p, s, sp, fb, t, d, hz) = e.components;
+            EntityData { marker: e.marker, components: (h, p, s, sp, fb, t, d, hz, None) }
+        })
+        .collect();
+    Level::new(&info, entities)
+}
+
+// v3 added moving platforms
+fn migrate_v2(level: Level<EntitiesV2>) -> Level<EntitiesV3> {
+    let info = level.info();
+    let entities = level.entities
+        .into_iter()
+        .map(|e| {
+            let (h, p, s, sp, fb, t, d, hz, ow) = e.components;
+            EntityData { marker: e.marker, components: (h, p, s, sp, fb, t, d, hz, ow, None) }
+        })
+        .collect();
+    Level::new(&info, entities)
+}
+
+// v4 gave falling blocks a delay and respawn time in place of the frame count
+fn migrate_v3(level: Level<EntitiesV3>) -> Level<EntitiesV4> {
+    let info = level.info();
+    let entities = level.entities
+        .into_iter()
+        .map(|e| {
+            let (h, p, s, sp, fb, t, d, hz, ow, mp) = e.components;
+            let fb = fb.map(|_| FallingBlock::default());
+            EntityData { marker: e.marker, components: (h, p, s, sp, fb, t, d, hz, ow, mp) }
+        })
+        .collect();
+    Level::new(&info, entities)
+}
+
+// v5 added slopes
+fn migrate_v4(level: Level<EntitiesV4>) -> Level<EntitiesV5> {
+    let info = level.info();
+    let entities = level.entities
+        .into_iter()
+        .map(|e| {
+            let (h, p, s, sp, fb, t, d, hz, ow, mp) = e.components;
+            EntityData { marker: e.marker, components: (h, p, s, sp, fb, t, d, hz, ow, mp, None) }
+        })
+        .collect();
+    Level::new(&info, entities)
+}
+
+// v6 added surface materials
+fn migrate_v5(level: Level<EntitiesV5>) -> Level<EntitiesV6> {
+    let info = level.info();
+    let entities = level.entities
+        .into_iter()
+        .map(|e| {
+            let (h, p, s, sp, fb, t, d, hz, ow, mp, sl) = e.components;
+            EntityData { marker: e.marker, components: (h, p, s, sp, fb, t, d, hz, ow, mp, sl, None) }
+        })
+        .collect();
+    Level::new(&info, entities)
+}
+
+// v7 dropped the falling block trigger, it follows the block's hitbox instead
+fn migrate_v6(level: Level<EntitiesV6>) -> Level<EntitiesV7> {
+    let info = level.info();
+    let entities = level.entities
+        .into_iter()
+        .map(|e| {
+            let (h, p, s, sp, fb, _, d, hz, ow, mp, sl, su) = e.components;
+            EntityData { marker: e.marker, components: (h, p, s, sp, fb, d, hz, ow, mp, sl, su) }
+        })
+        .collect();
+    Level::new(&info, entities)
+}
+
+// Only reads the components of each entity, to count them
+#[derive(Deserialize)]
+struct FieldsProbe {
+    components: ron::Value
+}
+
+// Parses a v0 file as whichever of its layouts has as many components per entity
+fn read_v0(contents: &[u8]) -> Result<EntitiesV1, LevelError> {
+    let probe: Vec<FieldsProbe> = ron::de::from_bytes(contents)?;
+    let fields = match probe.first().map(|e| &e.components) {
+        Some(ron::Value::Seq(fields)) => fields.len(),
+        Some(_) => return Err(ron::de::Error::Message("expected a tuple of components".to_string()).into()),
+        // an empty list reads the same in any layout
+        None => 8
+    };
+    match fields {
+        3 => Ok(migrate_v0_full(migrate_v0_spawn(ron::de::from_bytes(contents)?))),
+        4 => Ok(migrate_v0_full(ron::de::from_bytes(contents)?)),
+        8 => Ok(ron::de::from_bytes(contents)?),
+        n => Err(ron::de::Error::Message(format!("no unversioned layout has {} components", n)).into())
+    }
+}
+
+fn file_version(contents: &[u8]) -> Result<u32, LevelError> {
+    match ron::de::from_bytes::<VersionProbe>(contents) {
+        Ok(probe) => Ok(probe.version),
+        Err(e) => {
+            let bare_list = contents.iter().find(|b| !b.is_ascii_whitespace()) == Some(&b'[');
+            if bare_list {
+                Ok(0)
+            } else {
+                Err(e.into())
+            }
+        }
+    }
+}
+
+// Parses a level file of any known version and upgrades it to the current layout
+fn read_level(contents: &[u8]) -> Result<Level<EntitiesV7>, LevelError> {
+    match file_version(contents)? {
+        0 => Ok(migrate_v6(migrate_v5(migrate_v4(migrate_v3(migrate_v2(migrate_v1(migrate_v0(read_v0(contents)?)))))))),
+        1 => Ok(migrate_v6(migrate_v5(migrate_v4(migrate_v3(migrate_v2(migrate_v1(ron::de::from_bytes(contents)?))))))),
+        2 => Ok(migrate_v6(migrate_v5(migrate_v4(migrate_v3(migrate_v2(ron::de::from_bytes(contents)?)))))),
+        3 => Ok(migrate_v6(migrate_v5(migrate_v4(migrate_v3(ron::de::from_bytes(contents)?))))),
+        4 => Ok(migrate_v6(migrate_v5(migrate_v4(ron::de::from_bytes(contents)?)))),
+        5 => Ok(migrate_v6(migrate_v5(ron::de::from_bytes(contents)?))),
+        6 => Ok(migrate_v6(ron::de::from_bytes(contents)?)),
+        7 => Ok(ron::de::from_bytes(contents)?),
+        v => Err(LevelError::Version(v))
+    }
+}
+
+// Returns the level's info and the entity list in the form DeserializeComponents reads
+pub fn load(path: impl AsRef<Path>) -> Result<(LevelInfo, String), LevelError> {
+    let level = read_level(&fs::read(path)?)?;
+    let entities = ron::ser::to_string(&level.entities)?;
+    Ok((level.info(), entities))
+}
+
+pub fn save<E: Serialize>(path: impl AsRef<Path>, level: &Level<E>) -> Result<(), LevelError> {
+    let path = path.as_ref();
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let mut serializer = ron::ser::Serializer::new(Some(Default::default()), true);
+    level.serialize(&mut serializer)?;
+    fs::write(path, serializer.into_output_string())?;
+    Ok(())
+}
+
+pub fn path_for(name: &str) -> PathBuf {
+    Path::new(LEVEL_DIR).join(format!("{}.ron", name))
+}
+
+pub fn name_of(path: &Path) -> String {
+    path.file_stem().map(|s| s.to_string_lossy().into_owned()).unwrap_or_default()
+}
+
+// Every level file under LEVEL_DIR, sorted by name
+pub fn list() -> Result<Vec<PathBuf>, LevelError> {
+    let entries = match fs::read_dir(LEVEL_DIR) {
+        Ok(entries) => entries,
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e.into())
+    };
+    let mut files = Vec::new();
+    for entry in entries {
+        let path = entry?.path();
+        if path.extension().is_some_and(|ext| ext == "ron") {
+            files.push(path);
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+fn ensure_free(path: &Path) -> Result<(), LevelError> {
+    if path.exists() {
+        let e = io::Error::new(io::ErrorKind::AlreadyExists, format!("{} already exists", path.display()));
+        return Err(e.into());
+    }
+    Ok(())
+}
+
+pub fn create(name: &str) -> Result<PathBuf, LevelError> {
+    let path = path_for(name);
+    ensure_free(&path)?;
+    let info = LevelInfo { name: name.to_string(), ..LevelInfo::default() };
+    save(&path, &Level::new(&info, EntitiesV7::new()))?;
+    Ok(path)
+}
+
+// Moves the file and updates the name stored inside it
+pub fn rename(path: &Path, name: &str) -> Result<PathBuf, LevelError> {
+    let to = path_for(name);
+    ensure_free(&to)?;
+    let mut level = read_level(&fs::read(path)?)?;
+    level.version = LEVEL_VERSION;
+    level.name = name.to_string();
+    save(&to, &level)?;
+    fs::remove_file(path)?;
+    Ok(to)
+}
+
+pub fn duplicate(path: &Path) -> Result<PathBuf, LevelError> {
+    let mut level = read_level(&fs::read(path)?)?;
+    let base = format!("{}-copy", name_of(path));
+    let name = (1..)
+        .map(|n| if n == 1 { base.clone() } else { format!("{}{}", base, n) })
+        .find(|name| !path_for(name).exists())
+        .unwrap();
+    let to = path_for(&name);
+    level.version = LEVEL_VERSION;
+    level.name = name;
+    save(&to, &level)?;
+    Ok(to)
+}
+
+pub fn delete(path: &Path) -> Result<(), LevelError> {
+    fs::remove_file(path)?;
+    Ok(())
+}
+
+// Moves a level left over from before LEVEL_DIR into it as the default level, as long as
+// LEVEL_DIR has no levels yet. Returns where it went.
+pub fn adopt_legacy() -> Result<Option<PathBuf>, LevelError> {
+    let legacy = Path::new(LEGACY_LEVEL);
+    if !legacy.exists() || !list()?.is_empty() {
+        return Ok(None);
+    }
+    let to = CurrentLevel::default().0;
+    fs::create_dir_all(LEVEL_DIR)?;
+    fs::rename(legacy, &to)?;
+    Ok(Some(to))
+}
+
+// The file the editor saves to and loads from
+pub struct CurrentLevel(pub PathBuf);
+
+impl Default for CurrentLevel {
+    fn default() -> Self {
+        CurrentLevel(path_for("untitled"))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BrowserAction {
+    New,
+    Duplicate,
+    Rename,
+    Delete
+}
+
+pub const BROWSER_ACTIONS: [(BrowserAction, rIconDescription); 4] = [
+    (BrowserAction::New, rIconDescription::RICON_FILE_NEW),
+    (BrowserAction::Duplicate, rIconDescription::RICON_FILE_COPY),
+    (BrowserAction::Rename, rIconDescription::RICON_PENCIL),
+    (BrowserAction::Delete, rIconDescription::RICON_FILE_DELETE),
+];
+
+const PANEL_X: f32 = 60.;
+const PANEL_Y: f32 = 150.;
+const PANEL_WIDTH: f32 = 240.;
+const ROW_HEIGHT: f32 = 24.;
+const ROWS_TOP: f32 = 50.;
+
+// The in-editor list of level files, opened from the folder icon
+#[derive(Debug, Default)]
+pub struct LevelBrowser {
+    pub open: bool,
+    pub files: Vec<PathBuf>,
+    pub selected: Option<usize>,
+    // a name being typed in for a new or renamed level
+    pub entry: Option<(BrowserAction, String)>
+}
+
+impl LevelBrowser {
+    pub fn toggle(&mut self) -> Result<(), LevelError> {
+        self.open = !self.open;
+        self.entry = None;
+        if self.open {
+            self.refresh(None)?;
+        }
+        Ok(())
+    }
+
+    // Re-reads the directory, keeping `select` highlighted if it is given
+    pub fn refresh(&mut self, select: Option<&Path>) -> Result<(), LevelError> {
+        self.files = list()?;
+        self.selected = select.and_then(|path| self.files.iter().position(|f| f == path));
+        Ok(())
+    }
+
+    pub fn selected_file(&self) -> Option<&PathBuf> {
+        self.selected.and_then(|i| self.files.get(i))
+    }
+
+    pub fn is_typing(&self) -> bool {
+        self.open && self.entry.is_some()
+    }
+
+    pub fn panel(&self) -> Hitbox {
+        Hitbox {
+            position: Position::new(PANEL_X, PANEL_Y),
+            width: PANEL_WIDTH,
+            height: ROWS_TOP + ROW_HEIGHT * (self.files.len() + 1) as f32
+        }
+    }
+
+    pub fn covers(&self, point: Position) -> bool {
+        self.open && self.panel().point_inside_rec(point)
+    }
+
+    pub fn action_button(&self, index: usize) -> Hitbox {
+        Hitbox::new(PANEL_X + 8. + 40. * index as f32, PANEL_Y + 8.)
+    }
+
+    pub fn row(&self, index: usize) -> Hitbox {
+        Hitbox {
+            position: Position::new(PANEL_X, PANEL_Y + ROWS_TOP + ROW_HEIGHT * index as f32),
+            width: PANEL_WIDTH,
+            height: ROW_HEIGHT
+        }
+    }
+
+    pub fn action_at(&self, point: Position) -> Option<BrowserAction> {
+        BROWSER_ACTIONS
+            .iter()
+            .enumerate()
+            .find(|(i, _)| self.action_button(*i).point_inside_rec(point))
+            .map(|(_, (action, _))| *action)
+    }
+
+    pub fn row_at(&self, point: Position) -> Option<usize> {
+        (0..self.files.len()).find(|i| self.row(*i).point_inside_rec(point))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::components::FALL_DELAY;
+
+    type EntityV7 = EntityData<SimpleMarker<NetworkSync>, ComponentsV7>;
+
+    fn fixture(name: &str) -> Level<EntitiesV7> {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/levels").join(name);
+        match read_level(&fs::read(&path).unwrap()) {
+            Ok(level) => level,
+            Err(e) => panic!("{}: {}", path.display(), e)
+        }
+    }
+
+    fn by_sprite<'a>(level: &'a Level<EntitiesV7>, name: &str) -> &'a EntityV7 {
+        level.entities
+            .iter()
+            .find(|e| e.components.2.as_ref().is_some_and(|s| s.name == name))
+            .unwrap_or_else(|| panic!("no {} in the level", name))
+    }
+
+    // What every version down to the first bare list kept
+    fn check_blocks(level: &Level<EntitiesV7>) {
+        assert_eq!(level.version, LEVEL_VERSION);
+        assert!(level.entities.iter().all(|e| e.components.5.is_some()), "everything placed can be dragged");
+        let block = by_sprite(level, "block");
+        assert_eq!(block.components.0.unwrap().position, Position { x: 0., y: 320. });
+        assert!(block.components.4.is_none() && block.components.6.is_none());
+        let falling = by_sprite(level, "fallingblock");
+        assert_eq!(falling.components.4.unwrap().delay, FALL_DELAY);
+    }
+
+    fn check_spawn(level: &Level<EntitiesV7>) {
+        let spawn = by_sprite(level, "spawnpoint");
+        assert!(spawn.components.3.is_some());
+        assert_eq!(spawn.components.1, Some(Position { x: 16., y: 200. }));
+    }
+
+    fn check_spikes(level: &Level<EntitiesV7>) {
+        assert!(by_sprite(level, "spikeblock").components.6.is_some());
+    }
+
+    fn check_one_way(level: &Level<EntitiesV7>) {
+        assert!(by_sprite(level, "oneway").components.7.is_some());
+    }
+
+    fn check_platform(level: &Level<EntitiesV7>) {
+        let platform = by_sprite(level, "movingplatform").components.8.as_ref().unwrap();
+        assert_eq!(platform.waypoints, vec![Position { x: 64., y: 0. }, Position { x: 64., y: -64. }]);
+        assert!(platform.looping);
+        assert_eq!((platform.speed, platform.pause), (32., 1.));
+    }
+
+    fn check_slope(level: &Level<EntitiesV7>) {
+        let slope = by_sprite(level, "slopeup").components.9.unwrap();
+        assert_eq!((slope.left, slope.right), (0., 32.));
+    }
+
+    fn check_surface(level: &Level<EntitiesV7>) {
+        let surface = by_sprite(level, "ice").components.10.unwrap();
+        assert_eq!(surface.friction, 0.1);
+        assert_eq!(surface.speed, 1.);
+    }
+
+    #[test]
+    fn v0_drawn_only() {
+        let level = fixture("v0.ron");
+        assert_eq!(level.entities.len(), 3);
+        assert_eq!(level.name, LevelInfo::default().name);
+        check_blocks(&level);
+        check_spikes(&level);
+    }
+
+    #[test]
+    fn v0_with_spawn() {
+        let level = fixture("v0-spawn.ron");
+        assert_eq!(level.entities.len(), 3);
+        check_blocks(&level);
+        check_spawn(&level);
+    }
+
+    #[test]
+    fn v0_full() {
+        let level = fixture("v0-full.ron");
+        assert_eq!(level.entities.len(), 4);
+        check_blocks(&level);
+        check_spawn(&level);
+        check_spikes(&level);
+    }
+
+    #[test]
+    fn v1() {
+        let level = fixture("v1.ron");
+        assert_eq!(level.entities.len(), 4);
+        assert_eq!((level.name.as_str(), level.author.as_str()), ("fixture", "tester"));
+        assert_eq!(level.kill_plane, KILL_PLANE);
+        check_blocks(&level);
+        check_spawn(&level);
+        check_spikes(&level);
+    }
+
+    #[test]
+    fn v2() {
+        let level = fixture("v2.ron");
+        assert_eq!(level.entities.len(), 5);
+        check_blocks(&level);
+        check_spawn(&level);
+        check_one_way(&level);
+    }
+
+    #[test]
+    fn v3() {
+        let level = fixture("v3.ron");
+        assert_eq!(level.entities.len(), 6);
+        check_blocks(&level);
+        check_one_way(&level);
+        check_platform(&level);
+    }
+
+    #[test]
+    fn v4() {
+        let level = fixture("v4.ron");
+        assert_eq!(level.entities.len(), 6);
+        let falling = by_sprite(&level, "fallingblock").components.4.unwrap();
+        assert_eq!((falling.delay, falling.respawn), (0.5, Some(2.)));
+        check_platform(&level);
+    }
+
+    #[test]
+    fn v5() {
+        let level = fixture("v5.ron");
+        assert_eq!(level.entities.len(), 7);
+        check_platform(&level);
+        check_slope(&level);
+    }
+
+    #[test]
+    fn v6() {
+        let level = fixture("v6.ron");
+        assert_eq!(level.entities.len(), 8);
+        check_spawn(&level);
+        check_slope(&level);
+        check_surface(&level);
+    }
+
+    #[test]
+    fn v7() {
+        let level = fixture("v7.ron");
+        assert_eq!(level.entities.len(), 8);
+        check_spawn(&level);
+        check_spikes(&level);
+        check_one_way(&level);
+        check_platform(&level);
+        check_slope(&level);
+        check_surface(&level);
+    }
+
+    #[test]
+    fn fit_grows_every_way() {
+        let mut info = LevelInfo { width: 320., height: 320., ..LevelInfo::default() };
+        info.fit(&Hitbox::new(0., 0.));
+        assert_eq!(info.bounds(), Hitbox { position: Position::zero(), width: 320., height: 320. });
+        info.fit(&Hitbox::new(960., 64.));
+        info.fit(&Hitbox::new(-64., -32.));
+        assert_eq!(info.bounds(), Hitbox { position: Position::new(-64., -32.), width: 1056., height: 352. });
+        assert_eq!(info.kill_y(), 320. + KILL_PLANE);
+    }
+
+    #[test]
+    fn newer_version() {
+        let contents = b"Level(version: 99, name: \"\", author: \"\", width: 1, height: 1, entities: [])";
+        match read_level(contents) {
+            Err(LevelError::Version(99)) => {},
+            _ => panic!("a newer level should be refused")
+        }
+    }
+
+    #[test]
+    fn unknown_bare_layout() {
+        let contents = b"[(marker: (0), components: (None, None, None, None, None))]";
+        assert!(read_level(contents).is_err());
+    }
+}
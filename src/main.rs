@@ -1,10 +1,7 @@
 use raylib::prelude::*;
 use specs::{
     prelude::*,
-    saveload::{
-        DeserializeComponents, MarkedBuilder, SerializeComponents, SimpleMarker,
-        SimpleMarkerAllocator,
-    },
+    saveload::{SimpleMarker, SimpleMarkerAllocator},
 };
 use std::collections::HashMap;
 
@@ -16,17 +13,28 @@ extern crate strum_macros; // 0.10.0
 use strum::AsStaticRef;
 use strum::IntoEnumIterator;
 
-use crate::systems::{IconButtonsHandler, NetworkSync, Serialize, Deserialize, DrawSys, InputHandling, CollisionHandling, FallingBlockHandling, EditDragHandler, EditBtnHandle};
-use crate::components::{EditState, Icon, Sprite, ToolPalette, Rect, EditBtn, Transform, Position, Hitbox, Triggerbox, Moveable, PlatformController, FallingBlock, DragBox};
-use crate::utils::create_ent;
+use crate::systems::{CameraControl, FitLevel, SpatialIndexing, HazardHandling, LevelBounds, PlatformMovement, WaypointEditor, ModeToggle, ResetPlayer, TakeSnapshot, RestoreSnapshot, ClearLevel, ClipboardHandler, EraseHandler, HistoryHandler, IconButtonsHandler, LevelBrowserHandler, NetworkSync, Serialize, Deserialize, DrawSys, InputHandling, CollisionHandling, FallingBlockHandling, EditDragHandler, EditBtnHandle};
+use crate::components::{EditState, LevelSnapshot, Selection, StatusMessage, Time, InputState, Icon, Sprite, ToolPalette, Rect, EditBtn, Transform, Position, Hitbox, Moveable, PlatformController, Dash, FallingBlock, DragBox, Contacts, Hazard, MovingPlatform, OneWay, Slope, SpawnPoint, Surface};
+use crate::camera::Camera;
+use crate::history::{EditHistory, HISTORY_DEPTH};
+use crate::level::{CurrentLevel, LevelBrowser, LevelInfo};
+use crate::spatial::SpatialGrid;
 
+pub mod camera;
 pub mod components;
+pub mod headless;
+pub mod history;
+pub mod level;
+pub mod spatial;
 pub mod systems;
-pub mod utils;
 
 pub const COLOUR: Color = Color::new(34, 32, 52, 255);
 const WIDTH: i32 = 32 * 25;
 const HEIGHT: i32 = 32 * 20;
+// gameplay is simulated at a fixed rate no matter how fast frames are drawn
+const FIXED_DELTA: f32 = 1. / 60.;
+// tool buttons wrap onto a new row after this many
+const PALETTE_COLUMNS: usize = 5;
 
 fn window_should_close(world: &World) -> bool {
     let rl = world.read_resource::<RaylibHandle>();
@@ -36,36 +44,105 @@ fn window_should_close(world: &World) -> bool {
 fn should_save(world: &World) {
     let mut edit_state = world.write_resource::<EditState>();
     if edit_state.should_save {
-        Serialize.run_now(&world);
+        Serialize.run_now(world);
         edit_state.should_save = false;
     }
 }
 
-fn main() {
-    let (mut rl, thread) = raylib::init()
-        .size(WIDTH, HEIGHT)
-        .title("Hello, World")
-        .build();
-    rl.set_target_fps(60);
-    rl.set_mouse_scale(1., 1.);
-    let mut world = World::new();
-    let mut textures: HashMap<String, Texture2D> = {
-        let mut tm = HashMap::new();
+// Swaps the level in the world for the one in CurrentLevel
+fn should_load(world: &mut World) {
+    {
+        let mut edit_state = world.write_resource::<EditState>();
+        if !edit_state.should_load {
+            return;
+        }
+        edit_state.should_load = false;
+    }
+    // edits to the old level can't be undone into the new one
+    world.write_resource::<EditHistory>().clear();
+    ClearLevel.run_now(world);
+    world.maintain();
+    Deserialize.run_now(world);
+    world.maintain();
+}
 
-        for path in &["block", "fallingblock", "spikeblock"] {
-            let texture = rl.load_texture(&thread, &format!("assets/{}.png", path)).unwrap();
-            tm.insert(path.to_string(), texture);
+// Entering play snapshots the authored level, going back to editing restores it
+fn should_toggle(world: &mut World) {
+    let editting = {
+        let mut edit_state = world.write_resource::<EditState>();
+        if !edit_state.should_toggle {
+            return;
         }
-        tm
+        edit_state.should_toggle = false;
+        edit_state.editting = !edit_state.editting;
+        edit_state.editting
     };
-    
+    if editting {
+        restore_snapshot(world);
+    } else {
+        TakeSnapshot.run_now(world);
+    }
+    ResetPlayer.run_now(world);
+    // zooming is for editing, play starts on the player and editing picks up where it left off
+    if let Some(mut camera) = world.try_fetch_mut::<Camera>() {
+        if editting {
+            camera.stop_play();
+        } else {
+            let controllers = world.read_storage::<PlatformController>();
+            let hitboxes = world.read_storage::<Hitbox>();
+            let focus = (&controllers, &hitboxes)
+                .join()
+                .map(|(_, h)| h.position + Position::new(h.width / 2., h.height / 2.))
+                .next();
+            camera.start_play(focus);
+            camera.clamp_to(&world.read_resource::<LevelInfo>().bounds());
+        }
+    }
+}
+
+fn should_reset(world: &mut World) {
+    let reset = std::mem::replace(&mut world.write_resource::<EditState>().should_reset, false);
+    if reset {
+        restore_snapshot(world);
+        ResetPlayer.run_now(world);
+    }
+}
+
+fn restore_snapshot(world: &mut World) {
+    if world.read_resource::<LevelSnapshot>().0.is_none() {
+        return;
+    }
+    ClearLevel.run_now(world);
+    // the cleared entities must be gone before their markers are handed out again
+    world.maintain();
+    RestoreSnapshot.run_now(world);
+    world.maintain();
+}
+
+// Runs as many fixed steps as the banked frame time allows
+fn run_simulation(world: &mut World, simulation: &mut Dispatcher) {
+    while world.write_resource::<Time>().step() {
+        simulation.dispatch(world);
+        world.maintain();
+        should_reset(world);
+    }
+}
+
+fn setup_world(world: &mut World) {
     world.register::<Position>();
     world.register::<Transform>();
     world.register::<Hitbox>();
-    world.register::<Triggerbox>();
     world.register::<PlatformController>();
+    world.register::<Dash>();
     world.register::<Moveable>();
+    world.register::<Contacts>();
     world.register::<FallingBlock>();
+    world.register::<Hazard>();
+    world.register::<OneWay>();
+    world.register::<MovingPlatform>();
+    world.register::<Slope>();
+    world.register::<Surface>();
+    world.register::<SpawnPoint>();
     world.register::<EditBtn>();
     world.register::<Rect>();
     world.register::<DragBox>();
@@ -73,14 +150,80 @@ fn main() {
     world.register::<Icon>();
     world.register::<SimpleMarker<NetworkSync>>();
     world.insert(SimpleMarkerAllocator::<NetworkSync>::new());
-    world.insert(rl);
     world.insert(EditState::new());
+    world.insert(Time::new(FIXED_DELTA));
+    world.insert(InputState::default());
+    world.insert(SpatialGrid::new());
+    world.insert(LevelSnapshot::default());
+    world.insert(LevelInfo::default());
+    world.insert(StatusMessage::default());
+    world.insert(CurrentLevel::default());
+    world.insert(LevelBrowser::default());
+    world.insert(EditHistory::new(HISTORY_DEPTH));
+    world.insert(Selection::default());
+    world.insert(Camera::new(WIDTH as f32, HEIGHT as f32));
     world
         .create_entity()
-        .with(PlatformController::new())
+        .with(PlatformController::new(Position {x: 150., y: 250. }))
+        .with(Dash::new())
         .with(Moveable::new())
         .with(Moveable::new().to_hitbox(Position {x: 150., y: 250. }))
         .build();
+}
+
+// Systems that advance gameplay, stepped at FIXED_DELTA both in the window and headless
+fn simulation<'a, 'b>() -> Dispatcher<'a, 'b> {
+    specs::DispatcherBuilder::new()
+        .with(SpatialIndexing::default(), "spatial_indexing", &[])
+        .with(InputHandling, "input_handling", &[])
+        .with(CollisionHandling, "collision_handling", &["input_handling", "spatial_indexing"])
+        .with(FallingBlockHandling, "falling_block_handling", &["spatial_indexing"])
+        .with(PlatformMovement, "platform_movement", &["collision_handling"])
+        .with(LevelBounds, "level_bounds", &["platform_movement", "falling_block_handling"])
+        .with(HazardHandling, "hazard_handling", &["level_bounds"])
+        .build()
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let paths: Vec<&String> = args.iter().filter(|arg| !arg.starts_with("--")).collect();
+    if args.iter().any(|arg| arg == "--headless") {
+        let script = match paths.first() {
+            Some(script) => script,
+            None => {
+                eprintln!("Usage: --headless <script.ron> [level.ron]");
+                std::process::exit(1);
+            }
+        };
+        if let Err(e) = headless::run(script, paths.get(1).map(|level| level.as_str())) {
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    let (mut rl, thread) = raylib::init()
+        .size(WIDTH, HEIGHT)
+        .title("Hello, World")
+        .build();
+    rl.set_target_fps(60);
+    rl.set_mouse_scale(1., 1.);
+    let mut world = World::new();
+    let textures: HashMap<String, Texture2D> = {
+        let mut tm = HashMap::new();
+
+        for path in &["block", "fallingblock", "spikeblock", "oneway", "movingplatform", "slopeup", "slopedown", "halfslopeup", "halfslopedown", "ice", "mud", "conveyor", "bouncepad", "spawnpoint"] {
+            // DrawSys falls back to a coloured block for anything missing
+            match rl.load_texture(&thread, &format!("assets/{}.png", path)) {
+                Ok(texture) => { tm.insert(path.to_string(), texture); }
+                Err(e) => eprintln!("No texture for {}: {}", path, e)
+            }
+        }
+        tm
+    };
+    
+    setup_world(&mut world);
+    world.insert(rl);
     
     world
         .create_entity()
@@ -92,6 +235,19 @@ fn main() {
     world
         .create_entity()
         .with(Icon::new(raylib::consts::rIconDescription::RICON_FILE_SAVE_CLASSIC, Position { x: 15., y: 105.})).build();
+    world
+        .create_entity()
+        .with(Icon::new(raylib::consts::rIconDescription::RICON_FOLDER_OPEN, Position { x: 15., y: 150.})).build();
+
+    if let Some(path) = paths.first() {
+        world.insert(CurrentLevel(path.into()));
+    } else {
+        match level::adopt_legacy() {
+            Ok(Some(path)) => println!("Moved {} to {}", level::LEGACY_LEVEL, path.display()),
+            Ok(None) => {}
+            Err(e) => eprintln!("Could not move {} into {}: {}", level::LEGACY_LEVEL, level::LEVEL_DIR, e)
+        }
+    }
 
     Deserialize.run_now(&world);
         
@@ -104,8 +260,8 @@ fn main() {
                     width: 100.,
                     height: 50.,
                     position: Position {
-                        x: 100. * x as f32 + 250.,
-                        y: 100.
+                        x: 100. * (x % PALETTE_COLUMNS) as f32 + 250.,
+                        y: 80. * (x / PALETTE_COLUMNS) as f32 + 100.
                     }
                 },
                 text: tool.as_static().to_string().to_lowercase()
@@ -115,22 +271,40 @@ fn main() {
    
         
         
+    let mut simulation = simulation();
+    simulation.setup(&mut world);
+
     let mut dispatcher = specs::DispatcherBuilder::new()
         .with_thread_local(DrawSys { thread, textures })
-        .with(InputHandling, "input_handling", &[])
-        .with(CollisionHandling, "collision_handling", &["input_handling"])
-        .with(FallingBlockHandling, "falling_block_handling", &[])
-        .with(EditDragHandler, "edit_drag_handler", &[])
-        .with(EditBtnHandle { selected_ent: None, selected_ty: None }, "edit_btn_handle", &[])
+        .with(SpatialIndexing::default(), "spatial_indexing", &[])
+        .with(CameraControl::default(), "camera", &[])
+        .with(EditDragHandler, "edit_drag_handler", &["spatial_indexing", "camera"])
+        .with(EditBtnHandle { selected_ent: None, selected_ty: None }, "edit_btn_handle", &["spatial_indexing", "camera"])
         .with(IconButtonsHandler, "icon_button", &[])
+        .with(LevelBrowserHandler, "level_browser", &["icon_button"])
+        .with(EraseHandler, "erase_handler", &["spatial_indexing", "camera"])
+        .with(ClipboardHandler, "clipboard", &["edit_drag_handler"])
+        .with(WaypointEditor, "waypoint_editor", &["clipboard"])
+        .with(HistoryHandler, "history", &["edit_drag_handler", "edit_btn_handle", "erase_handler", "clipboard", "waypoint_editor", "icon_button"])
+        .with(FitLevel, "fit_level", &["history"])
+        .with(ModeToggle, "mode_toggle", &[])
         .build();
     dispatcher.setup(&mut world);
     
 
     loop {
+        let frame_time = {
+            let rl = world.read_resource::<RaylibHandle>();
+            world.write_resource::<InputState>().poll(&rl);
+            rl.get_frame_time()
+        };
+        world.write_resource::<Time>().advance(frame_time);
+        run_simulation(&mut world, &mut simulation);
         dispatcher.dispatch(&world);
         {
             should_save(&world);
+            should_load(&mut world);
+            should_toggle(&mut world);
             if window_should_close(&world) {
                 break;
             }
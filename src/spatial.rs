@@ -0,0 +1,137 @@
+use specs::world::Index;
+use std::collections::HashMap;
+
+use crate::components::{Hitbox, Position};
+
+pub const CELL_SIZE: f32 = 32.;
+
+type Cell = (i32, i32);
+
+// Buckets hitboxes into 32px cells so collision and picking only look at what's nearby.
+// Kept in sync with the Hitbox storage by the SpatialIndexing system.
+#[derive(Debug, Default)]
+pub struct SpatialGrid {
+    cells: HashMap<Cell, Vec<Index>>,
+    bounds: HashMap<Index, Hitbox>
+}
+
+// Cells touched by a hitbox; boxes sitting exactly on a cell edge also land in the
+// neighbouring cell so things touching them are found
+fn cell_range(hitbox: &Hitbox) -> (Cell, Cell) {
+    let min = (
+        (hitbox.position.x / CELL_SIZE).floor() as i32,
+        (hitbox.position.y / CELL_SIZE).floor() as i32
+    );
+    let max = (
+        ((hitbox.position.x + hitbox.width) / CELL_SIZE).floor() as i32,
+        ((hitbox.position.y + hitbox.height) / CELL_SIZE).floor() as i32
+    );
+    (min, max)
+}
+
+fn cells((min, max): (Cell, Cell)) -> impl Iterator<Item = Cell> {
+    (min.0..=max.0).flat_map(move |x| (min.1..=max.1).map(move |y| (x, y)))
+}
+
+impl SpatialGrid {
+    pub fn new() -> Self {
+        SpatialGrid::default()
+    }
+
+    pub fn insert(&mut self, id: Index, hitbox: Hitbox) {
+        if let Some(old) = self.bounds.get(&id) {
+            if cell_range(old) == cell_range(&hitbox) {
+                self.bounds.insert(id, hitbox);
+                return;
+            }
+            self.remove(id);
+        }
+        for cell in cells(cell_range(&hitbox)) {
+            self.cells.entry(cell).or_default().push(id);
+        }
+        self.bounds.insert(id, hitbox);
+    }
+
+    pub fn remove(&mut self, id: Index) {
+        if let Some(old) = self.bounds.remove(&id) {
+            for cell in cells(cell_range(&old)) {
+                if let Some(ids) = self.cells.get_mut(&cell) {
+                    ids.retain(|i| *i != id);
+                    if ids.is_empty() {
+                        self.cells.remove(&cell);
+                    }
+                }
+            }
+        }
+    }
+
+    // Ids of everything sharing a cell with `area`; callers still need to test the actual boxes
+    pub fn query(&self, area: &Hitbox) -> Vec<Index> {
+        let mut found: Vec<Index> = cells(cell_range(area))
+            .filter_map(|cell| self.cells.get(&cell))
+            .flatten()
+            .copied()
+            .collect();
+        found.sort_unstable();
+        found.dedup();
+        found
+    }
+
+    pub fn query_point(&self, point: Position) -> Vec<Index> {
+        self.query(&Hitbox {
+            position: point,
+            width: 0.,
+            height: 0.
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn insert_and_query() {
+        let mut grid = SpatialGrid::new();
+        grid.insert(1, Hitbox::new(0., 0.));
+        grid.insert(2, Hitbox::new(320., 320.));
+        assert_eq!(grid.query(&Hitbox::new(16., 16.)), vec![1]);
+        assert_eq!(grid.query_point(Position::new(330., 330.)), vec![2]);
+        assert!(grid.query(&Hitbox::new(160., 160.)).is_empty());
+    }
+
+    #[test]
+    fn edge_touching_is_found() {
+        let mut grid = SpatialGrid::new();
+        grid.insert(1, Hitbox::new(0., 0.));
+        // the box ends exactly on the next cell, so something standing there still finds it
+        assert_eq!(grid.query(&Hitbox::new(32., 0.)), vec![1]);
+    }
+
+    #[test]
+    fn move_updates_cells() {
+        let mut grid = SpatialGrid::new();
+        grid.insert(1, Hitbox::new(0., 0.));
+        grid.insert(1, Hitbox::new(256., 0.));
+        assert!(grid.query(&Hitbox::new(-16., 0.)).is_empty());
+        assert_eq!(grid.query(&Hitbox::new(256., 0.)), vec![1]);
+    }
+
+    #[test]
+    fn remove_clears_cells() {
+        let mut grid = SpatialGrid::new();
+        grid.insert(1, Hitbox::new(0., 0.));
+        grid.insert(2, Hitbox::new(8., 8.));
+        grid.remove(1);
+        assert_eq!(grid.query(&Hitbox::new(0., 0.)), vec![2]);
+        grid.remove(2);
+        assert!(grid.cells.is_empty() && grid.bounds.is_empty());
+    }
+
+    #[test]
+    fn query_is_deduplicated() {
+        let mut grid = SpatialGrid::new();
+        grid.insert(1, Hitbox { width: 96., height: 96., position: Position::zero() });
+        assert_eq!(grid.query(&Hitbox { width: 96., height: 96., position: Position::zero() }), vec![1]);
+    }
+}
@@ -1,15 +1,19 @@
-use crate::components::{EditState, Icon, ToolPalette, Sprite, CollisionsPoint, FallingBlock, Hitbox, Moveable, PlatformController, Triggerbox, EditBtn, DragBox, Position};
+use crate::components::{Dash, EditState, FallState, Icon, ToolPalette, Sprite, CollisionsPoint, FallingBlock, Hitbox, Moveable, PlatformController, DROP_TIME, EditBtn, DragBox, Position, Time, InputState, Contact, Contacts, EPSILON, Hazard, MovingPlatform, OneWay, Slope, SpawnPoint, Surface, LevelSnapshot, StatusMessage, Selection, SelectionDrag, CollisionsRec};
+use crate::level::{self, BrowserAction, Components, CurrentLevel, Level, LevelBrowser, LevelError, LevelInfo, BROWSER_ACTIONS};
+use crate::camera::Camera;
+use crate::history::{Change, Edit, EditHistory, SavedEntity};
+use crate::spatial::SpatialGrid;
+use std::cmp::Ordering;
 use raylib::consts::KeyboardKey::*;
 use raylib::consts::MouseButton::*;
 use raylib::prelude::*;
 use specs::{
     prelude::*,
     saveload::{
-        DeserializeComponents, MarkedBuilder, MarkerAllocator, SerializeComponents, SimpleMarker,
+        DeserializeComponents, Marker, MarkerAllocator, SerializeComponents, SimpleMarker,
         SimpleMarkerAllocator,
     },
 };
-use std::{convert::Infallible, fmt};
 use strum::AsStaticRef;
 use std::collections::HashMap;
 
@@ -22,399 +26,1689 @@ pub struct DrawSys {
 }
 impl<'a> System<'a> for DrawSys {
     type SystemData = (
-        WriteExpect<'a, EditState>,
+        ReadExpect<'a, EditState>,
         WriteExpect<'a, RaylibHandle>,
         ReadStorage<'a, Sprite>,
         ReadStorage<'a, Hitbox>,
         ReadStorage<'a, Position>,
         ReadStorage<'a, EditBtn>,
-        ReadStorage<'a, Icon>
+        ReadStorage<'a, Icon>,
+        ReadExpect<'a, Time>,
+        ReadStorage<'a, Moveable>,
+        ReadStorage<'a, PlatformController>,
+        ReadStorage<'a, SpawnPoint>,
+        WriteExpect<'a, StatusMessage>,
+        ReadExpect<'a, LevelBrowser>,
+        ReadExpect<'a, CurrentLevel>,
+        ReadExpect<'a, Selection>,
+        ReadExpect<'a, InputState>,
+        ReadExpect<'a, Camera>,
+        ReadExpect<'a, LevelInfo>,
+        ReadStorage<'a, MovingPlatform>,
+        ReadStorage<'a, FallingBlock>
     );
 
-    fn run(&mut self, (mut edit_state, mut rl, sprites, hitboxs, positions, edit_btns, icons): Self::SystemData) {
+    fn run(&mut self, (edit_state, mut rl, sprites, hitboxs, positions, edit_btns, icons, time, moveables, controllers, spawn_points, mut message, browser, current, selection, input, camera, info, moving_platforms, falling_blocks): Self::SystemData) {
+        message.tick(rl.get_frame_time());
         let width = rl.get_screen_width();
         let height = rl.get_screen_height();
-        let cols = width / 32;
-        let rows = height / 32;
-        if rl.is_key_pressed(KEY_P) {
-            edit_state.editting = !edit_state.editting;
-        }
         let mut d = rl.begin_drawing(&self.thread);
         d.clear_background(crate::COLOUR);
-        for (sprite, hitbox, position) in (&sprites, (&hitboxs).maybe(), (&positions).maybe()).join() {
-            if hitbox.is_some() || position.is_some() {
-                
-                let texture = self.textures.get(&sprite.name).unwrap_or_else(|| panic!("cannot find sprite for {}", sprite.name));
-                let v_pos: Vector2 = if let Some(hitbox) = hitbox {
-                    hitbox.position.into()
+        // the simulation only runs in play mode, so previous positions are stale while editing
+        let alpha = if edit_state.editting { 1. } else { time.alpha };
+        {
+            // everything in the level is drawn in world space through the camera
+            let mut d = d.begin_mode2D(&*camera);
+            for (sprite, hitbox, position, moveable, spawn, falling, platform) in (&sprites, (&hitboxs).maybe(), (&positions).maybe(), (&moveables).maybe(), (&spawn_points).maybe(), (&falling_blocks).maybe(), (&moving_platforms).maybe()).join() {
+                if spawn.is_some() && !edit_state.editting {
+                    continue;
+                }
+                if hitbox.is_some() || position.is_some() {
+                    
+                    let v_pos: Vector2 = if let Some(hitbox) = hitbox {
+                        let shake = Position::new(falling.map_or(0., |f| f.shake()), 0.);
+                        let previous = moveable.and_then(|m| m.previous).or_else(|| platform.and_then(|p| p.previous));
+                        (interpolate(hitbox, previous, alpha) + shake).into()
+                    } else {
+                        let p = *position.unwrap();
+                        p.into()
+                    };
+                    match self.textures.get(&sprite.name) {
+                        Some(texture) => d.draw_texture_ex(texture, v_pos, 0., 1., Color::WHITE),
+                        None => d.draw_rectangle_v(v_pos, Vector2::new(32., 32.), placeholder_colour(&sprite.name))
+                    }
+                }
+               
+            }
+            for (controller, hitbox, moveable) in (&controllers, &hitboxs, &moveables).join() {
+                let pos = interpolate(hitbox, moveable.previous, alpha);
+                if controller.is_dead() {
+                    // shrink into the centre while the death state plays out
+                    let scale = controller.death_timer / DEATH_TIME;
+                    let (width, height) = (hitbox.width * scale, hitbox.height * scale);
+                    let x = pos.x + (hitbox.width - width) / 2.;
+                    let y = pos.y + (hitbox.height - height) / 2.;
+                    d.draw_rectangle_rec(Rectangle::new(x, y, width, height), Color::RED);
                 } else {
-                    let p = *position.unwrap();
-                    p.into()
-                };
-                d.draw_texture_ex(texture, v_pos, 0., 1., Color::WHITE);
+                    d.draw_rectangle_rec(Rectangle::new(pos.x, pos.y, hitbox.width, hitbox.height), Color::WHITE);
+                }
             }
-           
-        }
-        if DEBUG {
-            for h in hitboxs.join() {
-                let rec: Rectangle = h.clone().into();
-                d.draw_rectangle_lines_ex(rec, 2, Color::RED);
+            if DEBUG {
+                for h in hitboxs.join() {
+                    let rec: Rectangle = (*h).into();
+                    d.draw_rectangle_lines_ex(rec, 2, Color::RED);
+                }
+            }
+            if edit_state.editting {
+                // grid lines over whatever part of the world is on screen
+                let top_left = camera.screen_to_world(Position::zero());
+                let bottom_right = camera.screen_to_world(Position::new(width as f32, height as f32));
+                let (left, top) = ((top_left.x / 32.).floor() as i32 * 32, (top_left.y / 32.).floor() as i32 * 32);
+                let (right, bottom) = (bottom_right.x.ceil() as i32, bottom_right.y.ceil() as i32);
+                let border_color = Color::new(Color::GRAY.r, Color::GRAY.g, Color::GRAY.b, 50);
+                for x in (left..=right).step_by(32) {
+                    d.draw_line(x, top, x, bottom, border_color);
+                }
+                for y in (top..=bottom).step_by(32) {
+                    d.draw_line(left, y, right, y, border_color);
+                }
+                d.draw_rectangle_lines_ex(info.bounds(), 2, Color::LIGHTGRAY);
+                for (platform, hitbox) in (&moving_platforms, &hitboxs).join() {
+                    let centre = Position::new(hitbox.width / 2., hitbox.height / 2.);
+                    let points: Vec<Vector2> = platform.points(hitbox.position).into_iter().map(|p| (p + centre).into()).collect();
+                    let closing = if platform.looping { points.last().copied() } else { None };
+                    let mut from = closing;
+                    for point in &points {
+                        if let Some(from) = from {
+                            d.draw_line_v(from, *point, Color::ORANGE);
+                        }
+                        d.draw_circle_v(*point, 4., Color::ORANGE);
+                        from = Some(*point);
+                    }
+                }
+                let kill_y = info.kill_y() as i32;
+                d.draw_line(info.left as i32, kill_y, (info.left + info.width) as i32, kill_y, Color::RED);
+
+                for entity in &selection.entities {
+                    if let Some(bounds) = editor_bounds(hitboxs.get(*entity), positions.get(*entity)) {
+                        d.draw_rectangle_lines_ex(bounds, 2, Color::YELLOW);
+                    }
+                }
+                if let Some(start) = selection.marquee {
+                    let area: Rectangle = marquee_area(start, input.world_mouse).into();
+                    d.draw_rectangle_rec(area, Color::new(255, 255, 255, 40));
+                    d.draw_rectangle_lines_ex(area, 1, Color::WHITE);
+                }
             }
         }
         if edit_state.editting {
             for edit_btn in edit_btns.join() {
-                let rec: Rectangle = edit_btn.bounds.clone().into();
+                let rec: Rectangle = edit_btn.bounds.into();
                 let texture_name = edit_btn.text.to_string().to_lowercase();
                 d.draw_rectangle(rec.x as i32, rec.y as i32, 70, 70, Color::BLUE);
                 // d.draw_text(&edit_btn.text, rec.x as i32 + 10, rec.y as i32 + 10, 20, Color::WHITE);
-                d.draw_texture_ex(self.textures.get(&*texture_name).unwrap(), Vector2::new(rec.x + 2., rec.y + 2.),0., 1.5, Color::WHITE)
-            }
-
-            let border_color = Color::new(Color::GRAY.r, Color::GRAY.g, Color::GRAY.b, 50);
-            for col in 0..cols {
-                d.draw_line(col * 32, 0, col * 32, height,border_color);  
-            }
-
-            for row in 0..rows {   
-                d.draw_line(0, row * 32, width, row * 32, border_color);
+                let pos = Vector2::new(rec.x + 2., rec.y + 2.);
+                match self.textures.get(&*texture_name) {
+                    Some(texture) => d.draw_texture_ex(texture, pos, 0., 1.5, Color::WHITE),
+                    None => d.draw_rectangle_v(pos, Vector2::new(48., 48.), placeholder_colour(&texture_name))
+                }
             }
 
             for icon in icons.join() {
                 let pos: Vector2 = icon.position.into();
+                let active = edit_state.erasing && icon.icon.0 == raylib::consts::rIconDescription::RICON_BIN;
                 d.draw_icon(
                     icon.icon.0,
                     pos,
                     2,
-                    Color::WHITE,
+                    if active { Color::RED } else { Color::WHITE },
                 );
             }
-           
+
+            if browser.open {
+                let panel: Rectangle = browser.panel().into();
+                d.draw_rectangle_rec(panel, Color::new(0, 0, 0, 200));
+                for (i, (_, icon)) in BROWSER_ACTIONS.iter().enumerate() {
+                    let pos: Vector2 = browser.action_button(i).position.into();
+                    d.draw_icon(*icon, pos, 2, Color::WHITE);
+                }
+                for (i, file) in browser.files.iter().enumerate() {
+                    let row: Rectangle = browser.row(i).into();
+                    if browser.selected == Some(i) {
+                        d.draw_rectangle_rec(row, Color::BLUE);
+                    }
+                    let marker = if *file == current.0 { "* " } else { "" };
+                    d.draw_text(&format!("{}{}", marker, level::name_of(file)), row.x as i32 + 8, row.y as i32 + 4, 16, Color::WHITE);
+                }
+                if let Some((action, name)) = &browser.entry {
+                    let row: Rectangle = browser.row(browser.files.len()).into();
+                    let label = if *action == BrowserAction::New { "new" } else { "rename" };
+                    d.draw_text(&format!("{}: {}_", label, name), row.x as i32 + 8, row.y as i32 + 4, 16, Color::YELLOW);
+                }
+            }
+        }
+        if let Some(text) = &message.text {
+            d.draw_rectangle(0, height - 30, width, 30, Color::new(0, 0, 0, 180));
+            d.draw_text(text, 10, height - 25, 20, Color::WHITE);
+        }
+    }
+}
+
+// Moves the camera: following the player in play, middle-mouse pan and wheel zoom while editing.
+// Also works out where the mouse is in the world for the editor systems.
+#[derive(Default)]
+pub struct CameraControl {
+    last_mouse: Option<Position>
+}
+impl<'a> System<'a> for CameraControl {
+    type SystemData = (
+        ReadExpect<'a, EditState>,
+        WriteExpect<'a, InputState>,
+        WriteExpect<'a, Camera>,
+        ReadExpect<'a, Time>,
+        ReadExpect<'a, LevelBrowser>,
+        ReadExpect<'a, LevelInfo>,
+        ReadStorage<'a, PlatformController>,
+        ReadStorage<'a, Hitbox>,
+        ReadStorage<'a, Moveable>
+    );
+
+    fn run(&mut self, (edit_state, mut input, mut camera, time, browser, info, controllers, hitboxes, moveables): Self::SystemData) {
+        let mouse_pos = input.mouse_position;
+        if edit_state.editting {
+            if let Some(last) = self.last_mouse {
+                if input.is_mouse_button_down(MOUSE_MIDDLE_BUTTON) {
+                    camera.pan(mouse_pos - last);
+                }
+            }
+            if input.wheel != 0. && !browser.covers(mouse_pos) {
+                camera.zoom_at(mouse_pos, input.wheel);
+            }
+        } else {
+            for (_, hitbox, moveable) in (&controllers, &hitboxes, &moveables).join() {
+                let centre = interpolate(hitbox, moveable.previous, time.alpha) + Position::new(hitbox.width / 2., hitbox.height / 2.);
+                camera.follow(centre, moveable.velocity.x, time.frame_delta);
+            }
+            camera.clamp_to(&info.bounds());
+        }
+        self.last_mouse = Some(mouse_pos);
+        input.world_mouse = camera.screen_to_world(mouse_pos);
+    }
+}
+
+// Keeps the SpatialGrid in step with every insert, move and removal of a Hitbox
+#[derive(Default)]
+pub struct SpatialIndexing {
+    reader: Option<ReaderId<ComponentEvent>>
+}
+impl<'a> System<'a> for SpatialIndexing {
+    type SystemData = (
+        Entities<'a>,
+        ReadStorage<'a, Hitbox>,
+        WriteExpect<'a, SpatialGrid>,
+    );
+
+    fn setup(&mut self, world: &mut World) {
+        Self::SystemData::setup(world);
+        self.reader = Some(WriteStorage::<Hitbox>::fetch(world).register_reader());
+        // anything created before the reader existed won't have produced an event for it
+        let entities = world.entities();
+        let hitboxes = world.read_storage::<Hitbox>();
+        let mut grid = world.write_resource::<SpatialGrid>();
+        for (entity, hitbox) in (&entities, &hitboxes).join() {
+            grid.insert(entity.id(), *hitbox);
+        }
+    }
+
+    fn run(&mut self, (entities, hitboxes, mut grid): Self::SystemData) {
+        let reader = self.reader.as_mut().expect("SpatialIndexing needs setup to be run");
+        let events: Vec<ComponentEvent> = hitboxes.channel().read(reader).cloned().collect();
+        for event in events {
+            match event {
+                ComponentEvent::Inserted(id) | ComponentEvent::Modified(id) => {
+                    if let Some(hitbox) = hitboxes.get(entities.entity(id)) {
+                        grid.insert(id, *hitbox);
+                    }
+                },
+                ComponentEvent::Removed(id) => grid.remove(id)
+            }
+        }
+    }
+}
+
+// What a sprite is drawn as when its texture couldn't be loaded
+fn placeholder_colour(name: &str) -> Color {
+    match name {
+        "block" => Color::GRAY,
+        "fallingblock" => Color::BEIGE,
+        "spikeblock" => Color::RED,
+        "oneway" => Color::LIGHTGRAY,
+        "movingplatform" => Color::ORANGE,
+        "slopeup" | "slopedown" | "halfslopeup" | "halfslopedown" => Color::DARKGRAY,
+        "ice" => Color::SKYBLUE,
+        "mud" => Color::DARKBROWN,
+        "conveyor" => Color::PURPLE,
+        "bouncepad" => Color::PINK,
+        "spawnpoint" => Color::LIME,
+        _ => Color::MAGENTA
+    }
+}
+
+fn interpolate(hitbox: &Hitbox, previous: Option<Position>, alpha: f32) -> Position {
+    match previous {
+        Some(previous) => previous.lerp(hitbox.position, alpha),
+        None => hitbox.position
+    }
+}
+
+pub struct ModeToggle;
+impl<'a> System<'a> for ModeToggle {
+    type SystemData = (
+        WriteExpect<'a, EditState>,
+        ReadExpect<'a, InputState>,
+        ReadExpect<'a, LevelBrowser>,
+    );
+
+    fn run(&mut self, (mut edit_state, input, browser): Self::SystemData) {
+        // P is just a letter while a level name is being typed
+        if input.is_key_pressed(KEY_P) && !browser.is_typing() {
+            edit_state.should_toggle = true;
         }
     }
 }
 
-const MAX_COYOTE_TIME: f32 = 15.;
+// Puts the player back on the level's spawn point, standing still
+pub struct ResetPlayer;
+impl<'a> System<'a> for ResetPlayer {
+    type SystemData = (
+        ReadStorage<'a, SpawnPoint>,
+        ReadStorage<'a, Position>,
+        WriteStorage<'a, PlatformController>,
+        WriteStorage<'a, Moveable>,
+        WriteStorage<'a, Hitbox>,
+        WriteStorage<'a, Dash>,
+    );
+
+    fn run(&mut self, (spawn_points, positions, mut controllers, mut moveables, mut hitboxes, mut dashes): Self::SystemData) {
+        let spawn = (&spawn_points, &positions).join().map(|(_, p)| *p).next();
+        for (controller, m, hitbox, dash) in (&mut controllers, &mut moveables, &mut hitboxes, (&mut dashes).maybe()).join() {
+            if let Some(spawn) = spawn {
+                controller.spawn = spawn;
+            }
+            controller.death_timer = 0.;
+            controller.can_jump = false;
+            // no coyote jump out of an airborne spawn, and a jump still held from before needs pressing again
+            controller.air_time = controller.coyote_time;
+            controller.jump_held = true;
+            controller.buffered = 0.;
+            controller.jumping = false;
+            controller.lockout = 0.;
+            controller.dropping = 0.;
+            if let Some(dash) = dash {
+                dash.reset();
+            }
+            hitbox.position = controller.spawn;
+            m.velocity = Position::zero();
+            m.previous = None;
+            m.ground = Surface::default();
+        }
+    }
+}
+
+// Starts and runs a dash, returning true while it overrides the rest of the controls
+fn handle_dash(dash: &mut Dash, controller: &mut PlatformController, m: &mut Moveable, input: &InputState, delta: f32) -> bool {
+    dash.cooldown_left = (dash.cooldown_left - delta).max(0.);
+    let x = input.is_key_down(KEY_RIGHT) as i32 - input.is_key_down(KEY_LEFT) as i32;
+    // the last way it was pushed or moved, so a dash from standing still goes the right way
+    if x != 0 {
+        dash.facing = x as f32;
+    } else if m.velocity.x != 0. {
+        dash.facing = m.velocity.x.signum();
+    }
+    let held = input.is_key_down(DASH_KEY);
+    let pressed = held && !dash.held;
+    dash.held = held;
+
+    if dash.is_dashing() {
+        dash.time_left -= delta;
+        if dash.is_dashing() {
+            m.velocity = dash.velocity();
+            return true;
+        }
+        // don't carry the dash speed out of it
+        m.velocity = Position::zero();
+        return false;
+    }
+
+    if !pressed || dash.cooldown_left > 0. || (!controller.can_jump && dash.air_left == 0) {
+        return false;
+    }
+    let y = input.is_key_down(KEY_DOWN) as i32 - input.is_key_down(KEY_UP) as i32;
+    let direction = if x == 0 && y == 0 {
+        // no direction held, dash the way the player faces
+        Position::new(dash.facing, 0.)
+    } else {
+        Position::new(x as f32, y as f32) / (((x * x + y * y) as f32).sqrt())
+    };
+    if !controller.can_jump {
+        dash.air_left -= 1;
+    }
+    dash.direction = direction;
+    dash.time_left = dash.duration;
+    dash.cooldown_left = dash.cooldown;
+    controller.jumping = false;
+    controller.buffered = 0.;
+    controller.lockout = 0.;
+    m.velocity = dash.velocity();
+    true
+}
+
+// Moves `current` towards `target` by at most `step`
+fn approach(current: f32, target: f32, step: f32) -> f32 {
+    if current < target {
+        (current + step).min(target)
+    } else {
+        (current - step).max(target)
+    }
+}
+
+const DASH_KEY: KeyboardKey = KEY_X;
 const TIME_TO_JUMP_HEIGHT: f32 = 0.55;
 const JUMP_HEIGHT: f32 = 66.0;
-const HOZ_SPEED: f32 = 200.0;
 const GRAVITY: f32 = (JUMP_HEIGHT * 2.) / (TIME_TO_JUMP_HEIGHT * TIME_TO_JUMP_HEIGHT);
 const JUMP_VELOCITY: f32 = GRAVITY * TIME_TO_JUMP_HEIGHT;
-const PADDING: f32 = 0.05;
 pub struct InputHandling;
 impl<'a> System<'a> for InputHandling {
     type SystemData = (
         WriteExpect<'a, EditState>,
-        ReadExpect<'a, RaylibHandle>,
+        ReadExpect<'a, InputState>,
+        ReadExpect<'a, Time>,
         WriteStorage<'a, PlatformController>,
         WriteStorage<'a, Moveable>,
+        WriteStorage<'a, Dash>,
+        ReadStorage<'a, Contacts>,
+        ReadStorage<'a, OneWay>,
     );
 
-    fn run(&mut self, (edit_state, rl, mut platform_controller, mut moveable): Self::SystemData) {
+    fn run(&mut self, (edit_state, input, time, mut platform_controller, mut moveable, mut dashes, contacts, one_ways): Self::SystemData) {
         if edit_state.editting { return; }
-        for (controller, m) in (&mut platform_controller, &mut moveable).join() {
-            m.velocity.x = if rl.is_key_down(KEY_LEFT) {
-                -HOZ_SPEED
-            } else if rl.is_key_down(KEY_RIGHT) {
-                HOZ_SPEED
+        for (controller, m, dash, touching) in (&mut platform_controller, &mut moveable, (&mut dashes).maybe(), (&contacts).maybe()).join() {
+            if controller.is_dead() { continue; }
+            if let Some(dash) = dash {
+                if handle_dash(dash, controller, m, &input, time.fixed_delta) {
+                    continue;
+                }
+            }
+            // a wall jump keeps its kick for a moment so holding towards the wall doesn't undo it
+            if controller.lockout > 0. {
+                controller.lockout -= time.fixed_delta;
             } else {
-                0.0
-            };
+                let direction = if input.is_key_down(KEY_LEFT) {
+                    -1.
+                } else if input.is_key_down(KEY_RIGHT) {
+                    1.
+                } else {
+                    0.
+                };
+                // the ground underfoot changes how quickly and how fast it can run
+                let step = controller.acceleration(m.velocity.x, direction) * m.ground.friction * time.fixed_delta;
+                m.velocity.x = approach(m.velocity.x, direction * controller.max_speed * m.ground.speed, step);
+            }
+
+            // compared against the last step rather than the frame, several steps can share a frame
+            let held = input.is_key_down(KEY_SPACE);
+            if held && !controller.jump_held {
+                controller.buffered = controller.jump_buffer;
+            }
+            controller.jump_held = held;
+
+            controller.dropping = (controller.dropping - time.fixed_delta).max(0.);
+            // down + jump drops through one-way platforms, as long as nothing else is underfoot
+            let on_one_way = touching.is_some_and(|t| {
+                let mut ground = t.0.iter().filter(|c| c.normal.y < 0.).peekable();
+                ground.peek().is_some() && ground.all(|c| one_ways.get(c.entity).is_some())
+            });
+            if controller.buffered > 0. && input.is_key_down(KEY_DOWN) && on_one_way {
+                controller.dropping = DROP_TIME;
+                controller.buffered = 0.;
+                controller.can_jump = false;
+                controller.air_time = controller.coyote_time;
+            }
 
-            if rl.is_key_down(KEY_SPACE)
-                && (controller.can_jump || controller.coyote_time < MAX_COYOTE_TIME)
-            {
+            let wall_jump = controller.wall_jump_direction().filter(|_| controller.buffered > 0.);
+            if controller.buffered > 0. && controller.can_jump_now() {
                 m.velocity.y = -JUMP_VELOCITY;
                 controller.can_jump = false;
-                controller.coyote_time = MAX_COYOTE_TIME;
+                controller.air_time = controller.coyote_time;
+                controller.buffered = 0.;
+                controller.jumping = true;
+            } else if let Some(direction) = wall_jump {
+                m.velocity = Position::new(direction * controller.wall_kick, -JUMP_VELOCITY);
+                controller.lockout = controller.wall_lockout;
+                controller.buffered = 0.;
+                controller.jumping = true;
+            } else {
+                controller.buffered = (controller.buffered - time.fixed_delta).max(0.);
+            }
+
+            if controller.jumping && (m.velocity.y >= 0. || !held) {
+                if m.velocity.y < 0. {
+                    m.velocity.y *= controller.jump_cut;
+                }
+                controller.jumping = false;
             }
         }
     }
 }
 
+// A moveable can slide along up to this many surfaces in one step
+const MAX_SWEEPS: usize = 3;
+// landings slower than this don't bounce, so resting on a bounce pad doesn't jitter
+const MIN_BOUNCE: f32 = 60.;
+// extra distance feet are pulled down onto a slope, so float error can't leave them hovering
+const SLOPE_SNAP: f32 = 1.;
 pub struct CollisionHandling;
 impl<'a> System<'a> for CollisionHandling {
     type SystemData = (
         WriteExpect<'a, EditState>,
-        ReadExpect<'a, RaylibHandle>,
+        ReadExpect<'a, Time>,
         WriteStorage<'a, Moveable>,
         WriteStorage<'a, Hitbox>,
         Entities<'a>,
         WriteStorage<'a, PlatformController>,
+        WriteStorage<'a, Contacts>,
+        WriteExpect<'a, SpatialGrid>,
+        WriteStorage<'a, Dash>,
+        ReadStorage<'a, OneWay>,
+        ReadStorage<'a, Slope>,
+        ReadStorage<'a, Surface>,
     );
-    fn run(&mut self, (edit_state, rl, mut moveable, mut hitboxs, entities, mut controller): Self::SystemData) {
+    fn run(&mut self, (edit_state, time, mut moveable, mut hitboxs, entities, mut controller, mut contacts, mut grid, mut dashes, one_ways, slopes, surfaces): Self::SystemData) {
         if edit_state.editting { return; }
-        let delta = rl.get_frame_time();
+        let delta = time.fixed_delta;
         for (m, entity, mut control) in (&mut moveable, &entities, (&mut controller).maybe()).join()
         {
-            let hbs: Vec<Hitbox> = (&entities, &hitboxs)
-                .join()
-                .filter(|(e, _)| *e != entity)
-                .map(|(_, h)| *h)
+            let start = *hitboxs
+                .get(entity)
+                .expect("Moveable component needs hitbox");
+            m.previous = Some(start.position);
+            // the body stays where it died until it respawns
+            if control.as_ref().is_some_and(|c| c.is_dead()) {
+                continue;
+            }
+            // a dash moves in a straight line
+            let dashing = dashes.get(entity).is_some_and(|d| d.is_dashing());
+            if !dashing {
+                m.velocity.y += GRAVITY * delta;
+                if let Some(control) = &control {
+                    if control.wall_jump_direction().is_some() {
+                        m.velocity.y = m.velocity.y.min(control.wall_slide_speed);
+                    }
+                }
+            }
+            let mut motion = m.velocity * delta;
+            // a conveyor carries what stands on it along without changing its own speed
+            motion.x += m.ground.conveyor * delta;
+            // how far the ground can be from the feet and still be walked onto
+            let slope_reach = motion.x.abs() + motion.y.abs() + SLOPE_SNAP;
+
+            // only what lies inside the area swept this step can be hit, give or take following a slope
+            let swept = Hitbox {
+                position: Position::new(start.position.x + motion.x.min(0.), start.position.y + motion.y.min(0.) - slope_reach),
+                width: start.width + motion.x.abs(),
+                height: start.height + motion.y.abs() + slope_reach * 2.
+            };
+            // slopes are swept against the box their ramp fills
+            let hbs: Vec<(Entity, Hitbox)> = grid
+                .query(&swept)
+                .into_iter()
+                .map(|id| entities.entity(id))
+                .filter(|e| *e != entity)
+                .filter_map(|e| hitboxs.get(e).map(|h| (e, slopes.get(e).map_or(*h, |s| s.solid(h)))))
                 .collect();
-            // check vert movement
             let hitbox = hitboxs
                 .get_mut(entity)
                 .expect("Moveable component needs hitbox");
-            let mut pot_rec_x: Rectangle = hitbox.clone().into();
-            pot_rec_x.x += m.velocity.x * delta;
-            let mut pot_rec_y: Rectangle = hitbox.clone().into();
-            pot_rec_y.y += m.velocity.y * delta;
-            let mut collision_x = false;
-            let mut collision_y = false;
-            for h in hbs {
-                let rect = h.clone().into();
-                if pot_rec_x.check_collision_recs(&rect) {
-                    collision_x = true;
-                    m.velocity.x = 0.0;
-                }
-                if pot_rec_y.check_collision_recs(&rect) {
-                    collision_y = true;
-                    m.velocity.y = 0.0;
-                    // collision ground
-                    if pot_rec_y.y > hitbox.position.y {
-                        hitbox.position.y = rect.y - hitbox.height - PADDING;
-                        if let Some(control) = &mut control {
-                            control.coyote_time = 0.;
-                            control.can_jump = true;
-                        }
+            let dropping = control.as_ref().is_some_and(|c| c.dropping > 0.);
+            // walking over slopes keeps to the ground rather than bumping into the next tile or running off into the air
+            let snap_down = control.as_ref().is_some_and(|c| c.can_jump) && m.velocity.y >= 0. && !dropping;
+            let on_slope = contacts.get(entity).is_some_and(|t| t.0.iter().any(|c| c.normal.y < 0. && c.normal.x != 0.));
+            if snap_down {
+                let mut ahead = *hitbox;
+                ahead.position.x += motion.x;
+                if settle_feet(&mut ahead, &hbs, &slopes, slope_reach, true, on_slope).is_some() {
+                    let rise = ahead.position.y - hitbox.position.y;
+                    // climb before moving on, so the lip where a slope meets the next tile can't catch the leading edge
+                    let ceiling = hbs
+                        .iter()
+                        .filter(|(e, _)| slopes.get(*e).is_none() && one_ways.get(*e).is_none())
+                        .any(|(_, h)| hitbox.sweep(Position::new(0., rise), h).is_some());
+                    if rise < 0. && !ceiling {
+                        hitbox.position.y += rise;
+                        motion.y = 0.;
                     } else {
-                        hitbox.position.y = rect.y + rect.height + PADDING;
+                        motion.y = rise;
                     }
                 }
             }
-            if !collision_x {
-                hitbox.position.x = pot_rec_x.x;
+
+            // move until the first thing in the way, stop flush against it, then slide along it
+            let mut touching = Vec::new();
+            let mut landing = None;
+            for _ in 0..MAX_SWEEPS {
+                let hit = hbs
+                    .iter()
+                    .filter_map(|(e, h)| hitbox.sweep(motion, h).map(|sweep| (*e, h, sweep)))
+                    // one-way platforms only stop things landing on top of them
+                    .filter(|(e, _, sweep)| one_ways.get(*e).is_none() || (sweep.normal.y < 0. && !dropping))
+                    .filter(|(e, _, sweep)| slopes.get(*e).is_none_or(|s| s.blocks(sweep.normal)))
+                    .min_by(|(_, _, a), (_, _, b)| a.time.partial_cmp(&b.time).unwrap_or(Ordering::Equal));
+                match hit {
+                    Some((e, other, sweep)) => {
+                        hitbox.position += motion * sweep.time;
+                        hitbox.flush_against(other, sweep.normal);
+                        touching.push(Contact { entity: e, normal: sweep.normal });
+                        motion *= 1. - sweep.time;
+                        if sweep.normal.x != 0. {
+                            motion.x = 0.;
+                            m.velocity.x = 0.;
+                        } else {
+                            motion.y = 0.;
+                            if sweep.normal.y < 0. {
+                                landing = Some(m.velocity.y);
+                            }
+                            m.velocity.y = 0.;
+                        }
+                    }
+                    None => {
+                        hitbox.position += motion;
+                        break;
+                    }
+                }
+            }
+
+            if let Some(contact) = settle_feet(hitbox, &hbs, &slopes, slope_reach, snap_down, on_slope) {
+                m.velocity.y = m.velocity.y.min(0.);
+                touching.push(contact);
+            }
+
+            // with no gravity a dash never presses into the ground, so look for it right under the feet
+            if dashing && !touching.iter().any(|c| c.normal.y < 0.) {
+                let under = hbs
+                    .iter()
+                    .filter(|(e, _)| slopes.get(*e).is_none() && (one_ways.get(*e).is_none() || !dropping))
+                    .find(|(_, tile)| flush_under(hitbox, tile));
+                if let Some((e, _)) = under {
+                    touching.push(Contact { entity: *e, normal: Position::new(0., -1.) });
+                }
+            }
+
+            m.ground = standing_on(hitbox, &touching, &hbs, &surfaces);
+            // landing on something springy throws it back up
+            if let Some(speed) = landing {
+                if speed * m.ground.bounce > MIN_BOUNCE {
+                    m.velocity.y = -speed * m.ground.bounce;
+                }
             }
+            grid.insert(entity.id(), *hitbox);
 
-            if !collision_y {
-                if let Some(control) = &mut control {
-                    control.coyote_time += 1.;
+            let touching = Contacts(touching);
+            if let Some(control) = &mut control {
+                if touching.on_ground() {
+                    control.air_time = 0.;
+                    control.can_jump = true;
+                    if let Some(dash) = dashes.get_mut(entity) {
+                        dash.air_left = dash.air_dashes;
+                    }
+                } else {
+                    control.air_time += delta;
                     control.can_jump = false;
                 }
-                hitbox.position.y += m.velocity.y * delta;
-                m.velocity.y += GRAVITY * delta;
+                control.wall_left = touching.wall_left();
+                control.wall_right = touching.wall_right();
             }
+            let _ = contacts.insert(entity, touching);
         }
     }
 }
 
-const MAX_FALL_COUNT: u32 = 80;
+// The surface of the ground nearest the middle of the feet, plain ground when there is none
+fn standing_on(hitbox: &Hitbox, touching: &[Contact], nearby: &[(Entity, Hitbox)], surfaces: &ReadStorage<Surface>) -> Surface {
+    let middle = hitbox.position.x + hitbox.width / 2.;
+    let grounded: Vec<Entity> = touching.iter().filter(|c| c.normal.y < 0.).map(|c| c.entity).collect();
+    if grounded.is_empty() {
+        return Surface::default();
+    }
+    // only the first tile landed on is a contact, so take in the others the feet are flush with too
+    nearby
+        .iter()
+        .filter(|(e, tile)| grounded.contains(e) || flush_under(hitbox, tile))
+        .map(|(e, tile)| {
+            let off = (tile.position.x - middle).max(middle - tile.position.x - tile.width).max(0.);
+            (off, surfaces.get(*e).copied().unwrap_or_default())
+        })
+        .min_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(Ordering::Equal))
+        .map_or_else(Surface::default, |(_, surface)| surface)
+}
+
+// Whether the top of `tile` is level with the feet and under them
+fn flush_under(hitbox: &Hitbox, tile: &Hitbox) -> bool {
+    (tile.position.y - hitbox.position.y - hitbox.height).abs() < EPSILON
+        && tile.position.x < hitbox.position.x + hitbox.width
+        && tile.position.x + tile.width > hitbox.position.x
+}
+
+// Puts the feet on the highest surface under them that is within `reach`. Feet that sank into a slope
+// are lifted out of it; with `snap_down` feet above a slope are pulled down onto it too, and with
+// `off_slope` so are feet just above the top of a solid, for stepping off the bottom of a slope.
+fn settle_feet(hitbox: &mut Hitbox, nearby: &[(Entity, Hitbox)], slopes: &ReadStorage<Slope>, reach: f32, snap_down: bool, off_slope: bool) -> Option<Contact> {
+    let left = hitbox.position.x;
+    let right = left + hitbox.width;
+    let bottom = hitbox.position.y + hitbox.height;
+    nearby
+        .iter()
+        .filter(|(_, tile)| right > tile.position.x && left < tile.position.x + tile.width)
+        .filter_map(|(e, tile)| match slopes.get(*e) {
+            Some(slope) => {
+                // whichever edge of the feet is further up the ramp rests on it
+                let foot = if slope.right > slope.left { right } else { left };
+                let surface = slope.surface_y(tile, foot);
+                let sunk = bottom - surface;
+                if (sunk >= 0. || snap_down) && sunk.abs() <= reach {
+                    Some((surface, Contact { entity: *e, normal: slope.normal(tile) }))
+                } else {
+                    None
+                }
+            }
+            None if snap_down && off_slope && tile.position.y > bottom && tile.position.y - bottom <= reach => {
+                Some((tile.position.y, Contact { entity: *e, normal: Position::new(0., -1.) }))
+            }
+            None => None
+        })
+        .min_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(Ordering::Equal))
+        .map(|(surface, contact)| {
+            hitbox.position.y = surface - hitbox.height;
+            contact
+        })
+}
+
+// Moves platforms along their paths. Whatever stood on a platform last step rides along with it,
+// whatever it runs into is pushed ahead of it, and anything pushed into something solid is crushed.
+pub struct PlatformMovement;
+impl<'a> System<'a> for PlatformMovement {
+    type SystemData = (
+        ReadExpect<'a, EditState>,
+        ReadExpect<'a, Time>,
+        Entities<'a>,
+        WriteStorage<'a, MovingPlatform>,
+        WriteStorage<'a, Hitbox>,
+        WriteStorage<'a, Moveable>,
+        WriteStorage<'a, PlatformController>,
+        ReadStorage<'a, Contacts>,
+        ReadStorage<'a, OneWay>,
+        WriteExpect<'a, SpatialGrid>,
+    );
+
+    fn run(&mut self, (edit_state, time, entities, mut platforms, mut hitboxes, mut moveables, mut controllers, contacts, one_ways, mut grid): Self::SystemData) {
+        if edit_state.editting { return; }
+        for (platform_entity, platform) in (&entities, &mut platforms).join() {
+            let start = match hitboxes.get(platform_entity) {
+                Some(hitbox) => *hitbox,
+                None => continue
+            };
+            platform.previous = Some(start.position);
+            let position = platform.advance(start.position, time.fixed_delta);
+            let motion = position - start.position;
+            if motion == Position::zero() {
+                continue;
+            }
+            let platform_box = Hitbox { position, ..start };
+            if let Some(hitbox) = hitboxes.get_mut(platform_entity) {
+                *hitbox = platform_box;
+            }
+            grid.insert(platform_entity.id(), platform_box);
+
+            let bodies: Vec<(Entity, Hitbox, bool)> = (&entities, &moveables, &hitboxes, (&contacts).maybe(), (&controllers).maybe())
+                .join()
+                .filter(|(_, _, _, _, controller)| !controller.is_some_and(|c| c.is_dead()))
+                .map(|(e, _, h, touching, _)| {
+                    let riding = touching.is_some_and(|t| t.0.iter().any(|c| c.entity == platform_entity && c.normal.y < 0.));
+                    (e, *h, riding)
+                })
+                .collect();
+            for (entity, body, riding) in bodies {
+                let moved = if riding {
+                    carry(body, motion, |b| solid_overlap(&entities, &grid, &hitboxes, &one_ways, b, entity, platform_entity))
+                } else if platform_box.collision_rec(body) {
+                    push_out(body, &start, &platform_box, motion)
+                } else {
+                    continue;
+                };
+                if let Some(hitbox) = hitboxes.get_mut(entity) {
+                    *hitbox = moved;
+                }
+                grid.insert(entity.id(), moved);
+
+                if solid_overlap(&entities, &grid, &hitboxes, &one_ways, moved, entity, platform_entity) {
+                    match (controllers.get_mut(entity), moveables.get_mut(entity)) {
+                        (Some(controller), Some(m)) => kill(controller, m),
+                        _ => { let _ = entities.delete(entity); }
+                    }
+                }
+            }
+        }
+    }
+}
+
+// Moves a rider with its platform. If that would put it inside something it only keeps the
+// vertical part, so walls stop it sliding sideways but a ceiling still crushes it.
+fn carry(body: Hitbox, motion: Position, blocked: impl Fn(Hitbox) -> bool) -> Hitbox {
+    let carried = Hitbox { position: body.position + motion, ..body };
+    if blocked(carried) {
+        Hitbox { position: body.position + Position::new(0., motion.y), ..body }
+    } else {
+        carried
+    }
+}
+
+// Puts `body` flush against the face of the platform that ran into it
+fn push_out(body: Hitbox, start: &Hitbox, platform: &Hitbox, motion: Position) -> Hitbox {
+    let mut pushed = body;
+    let was_beside = start.position.x + start.width <= body.position.x || body.position.x + body.width <= start.position.x;
+    if was_beside && motion.x != 0. {
+        pushed.flush_against(platform, Position::new(motion.x.signum(), 0.));
+    } else {
+        pushed.flush_against(platform, Position::new(0., motion.y.signum()));
+    }
+    pushed
+}
+
+// Whether `body` overlaps anything solid apart from itself and the platform moving it; one-way
+// platforms never crush. Only overlaps deeper than EPSILON count, so resting flush is never a crush.
+fn solid_overlap(entities: &Entities, grid: &SpatialGrid, hitboxes: &WriteStorage<Hitbox>, one_ways: &ReadStorage<OneWay>, body: Hitbox, entity: Entity, platform: Entity) -> bool {
+    let inner = Hitbox {
+        position: Position::new(body.position.x + EPSILON, body.position.y + EPSILON),
+        width: body.width - EPSILON * 2.,
+        height: body.height - EPSILON * 2.
+    };
+    grid.query(&inner)
+        .into_iter()
+        .map(|id| entities.entity(id))
+        .filter(|e| *e != entity && *e != platform && one_ways.get(*e).is_none())
+        .any(|e| hitboxes.get(e).is_some_and(|h| h.collision_rec(inner)))
+}
+
+// Blocks shake for their delay once stepped on, then fall. Once out of the level they are
+// removed, or hidden until it's time to put them back where they were placed.
 pub struct FallingBlockHandling;
 impl<'a> System<'a> for FallingBlockHandling {
     type SystemData = (
         ReadExpect<'a, EditState>,
+        ReadExpect<'a, Time>,
+        ReadExpect<'a, LevelInfo>,
         WriteStorage<'a, Moveable>,
         WriteStorage<'a, Hitbox>,
-        WriteStorage<'a, Triggerbox>,
         WriteStorage<'a, PlatformController>,
         WriteStorage<'a, FallingBlock>,
         Entities<'a>,
+        ReadExpect<'a, SpatialGrid>,
     );
 
     fn run(
         &mut self,
-        (edit_state, mut moveable, hitboxs, triggers, controller, mut falling_blocks, entities): Self::SystemData,
+        (edit_state, time, info, mut moveable, mut hitboxs, controller, mut falling_blocks, entities, grid): Self::SystemData,
     ) {
         if edit_state.editting { return; }
-        for (entity, trigger, fb) in (&entities, &triggers, &mut falling_blocks).join() {
-            let has_movable = moveable.get(entity);
-            if has_movable.is_none() {
-                if !fb.should_fall {
-                    let trigger_rect: Rectangle = trigger.clone().into();
-                    for (_, hitbox) in (&controller, &hitboxs).join() {
-                        let rect: Rectangle = hitbox.clone().into();
-                        if rect.check_collision_recs(&trigger_rect) {
-                            fb.should_fall = true;
-                        }
+        let delta = time.fixed_delta;
+        for (entity, fb) in (&entities, &mut falling_blocks).join() {
+            match fb.state {
+                FallState::Resting => {
+                    let hitbox = match hitboxs.get(entity) {
+                        Some(hitbox) => *hitbox,
+                        None => continue
+                    };
+                    // taken from wherever the block is now, so moving it in the editor moves it too
+                    let area = FallingBlock::trigger_area(&hitbox);
+                    let stepped_on = grid.query(&area)
+                        .into_iter()
+                        .map(|id| entities.entity(id))
+                        .any(|other| controller.get(other).is_some() && hitboxs.get(other).is_some_and(|h| h.collision_rec(area)));
+                    if stepped_on {
+                        fb.state = FallState::Shaking(0.);
+                        fb.home = Some(hitbox);
+                    }
+                }
+                FallState::Shaking(time) => {
+                    if time + delta >= fb.delay {
+                        let _res = moveable.insert(entity, Moveable::new());
+                        fb.state = FallState::Falling;
+                    } else {
+                        fb.state = FallState::Shaking(time + delta);
+                    }
+                }
+                FallState::Falling => {
+                    let hitbox = match hitboxs.get(entity) {
+                        Some(hitbox) => *hitbox,
+                        None => continue
+                    };
+                    if hitbox.collision_rec(info.bounds()) {
+                        continue;
+                    }
+                    if fb.respawn.is_some() {
+                        moveable.remove(entity);
+                        hitboxs.remove(entity);
+                        fb.state = FallState::Gone(0.);
+                    } else {
+                        let _ = entities.delete(entity);
+                    }
+                }
+                FallState::Gone(time) => {
+                    let (respawn, home) = match (fb.respawn, fb.home) {
+                        (Some(respawn), Some(home)) => (respawn, home),
+                        _ => continue
+                    };
+                    let time = time + delta;
+                    fb.state = FallState::Gone(time);
+                    // wait for whatever is standing where it goes to move out of the way
+                    let blocked = grid.query(&home)
+                        .into_iter()
+                        .any(|id| hitboxs.get(entities.entity(id)).is_some_and(|h| h.collision_rec(home)));
+                    if time >= respawn && !blocked {
+                        let _ = hitboxs.insert(entity, home);
+                        fb.state = FallState::Resting;
                     }
-                } else if fb.count <= MAX_FALL_COUNT {
-                    fb.count += 1;
-                } else {
-                    let _res = moveable.insert(entity, Moveable::new()); 
                 }
             }
         }
     }
 }
 
+const DEATH_TIME: f32 = 0.6;
+pub struct HazardHandling;
+impl<'a> System<'a> for HazardHandling {
+    type SystemData = (
+        WriteExpect<'a, EditState>,
+        ReadExpect<'a, Time>,
+        WriteStorage<'a, PlatformController>,
+        WriteStorage<'a, Moveable>,
+        ReadStorage<'a, Contacts>,
+        ReadStorage<'a, Hazard>,
+    );
 
-pub struct EditDragHandler;
-impl<'a> System<'a> for EditDragHandler {
+    fn run(&mut self, (mut edit_state, time, mut controllers, mut moveables, contacts, hazards): Self::SystemData) {
+        if edit_state.editting { return; }
+        for (controller, m, touching) in (&mut controllers, &mut moveables, (&contacts).maybe()).join() {
+            if controller.is_dead() {
+                controller.death_timer -= time.fixed_delta;
+                if !controller.is_dead() {
+                    // respawning puts the level back the way it was authored as well
+                    edit_state.should_reset = true;
+                }
+            } else if touching.is_some_and(|t| t.0.iter().any(|c| hazards.get(c.entity).is_some())) {
+                kill(controller, m);
+            }
+        }
+    }
+}
+
+// Starts the death state; HazardHandling counts it down and resets the level
+fn kill(controller: &mut PlatformController, m: &mut Moveable) {
+    controller.death_timer = DEATH_TIME;
+    controller.can_jump = false;
+    m.velocity = Position::zero();
+    m.ground = Surface::default();
+}
+
+// Keeps the player between the level's sides. Anything falling past the kill plane is removed,
+// or killed if it's the player.
+pub struct LevelBounds;
+impl<'a> System<'a> for LevelBounds {
     type SystemData = (
         ReadExpect<'a, EditState>,
-        ReadExpect<'a, RaylibHandle>,
-        WriteStorage<'a, DragBox>,
-        WriteStorage<'a, Hitbox>
+        ReadExpect<'a, LevelInfo>,
+        Entities<'a>,
+        WriteStorage<'a, Hitbox>,
+        WriteStorage<'a, Moveable>,
+        WriteStorage<'a, PlatformController>,
     );
 
-    fn run(&mut self, (edit_state, rl, mut drag_boxes, mut hitboxes): Self::SystemData) {
-        if edit_state.editting {
-            let mouse_pouse = Position::from(rl.get_mouse_position());
-            for (drag_box, hitbox) in (&mut drag_boxes, &mut hitboxes).join() {
-                if rl.is_mouse_button_pressed(MOUSE_LEFT_BUTTON) && hitbox.point_inside_rec(mouse_pouse) {
-                    drag_box.drag_offset = mouse_pouse - hitbox.position;
-                    drag_box.dragging = true;
-                }
-                if drag_box.dragging {
-                    let mut pos = (mouse_pouse - drag_box.drag_offset) / 32.;
-                    pos.x = pos.x.round();
-                    pos.y = pos.y.round();
-                    hitbox.position = pos * 32.;
+    fn run(&mut self, (edit_state, info, entities, mut hitboxes, mut moveables, mut controllers): Self::SystemData) {
+        if edit_state.editting { return; }
+        let mut pushed = Vec::new();
+        for (entity, hitbox, m) in (&entities, &hitboxes, &mut moveables).join() {
+            match controllers.get_mut(entity) {
+                Some(controller) => {
+                    if hitbox.position.y > info.kill_y() {
+                        if !controller.is_dead() {
+                            kill(controller, m);
+                        }
+                        continue;
+                    }
+                    let x = hitbox.position.x.max(info.left).min(info.left + info.width - hitbox.width);
+                    if x != hitbox.position.x {
+                        m.velocity.x = 0.;
+                        pushed.push((entity, x));
+                    }
                 }
-    
-                if rl.is_mouse_button_released(MOUSE_LEFT_BUTTON) {
-                    drag_box.dragging = false;
+                None => {
+                    if hitbox.position.y > info.kill_y() {
+                        let _ = entities.delete(entity);
+                    }
                 }
             }
         }
+        for (entity, x) in pushed {
+            if let Some(hitbox) = hitboxes.get_mut(entity) {
+                hitbox.position.x = x;
+            }
+        }
     }
 }
 
-#[derive(Debug)]
-enum Combined {
-    Ron(ron::ser::Error),
-}
+// Grows the level's bounds around everything placed. Bounds only ever grow, so a level
+// keeps the size it was saved with even after the tiles at its edge are erased.
+pub struct FitLevel;
+impl<'a> System<'a> for FitLevel {
+    type SystemData = (
+        ReadExpect<'a, EditState>,
+        WriteExpect<'a, LevelInfo>,
+        ReadStorage<'a, Hitbox>,
+        ReadStorage<'a, Position>,
+        ReadStorage<'a, SimpleMarker<NetworkSync>>
+    );
 
-// Implementing the required `Display`-trait, by matching the `Combined` enum,
-// allowing different error types to be displayed.
-impl fmt::Display for Combined {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        match *self {
-            Combined::Ron(ref e) => write!(f, "{}", e),
+    fn run(&mut self, (edit_state, mut info, hitboxes, positions, markers): Self::SystemData) {
+        if !edit_state.editting { return; }
+        for (hitbox, position, _) in ((&hitboxes).maybe(), (&positions).maybe(), &markers).join() {
+            if let Some(area) = editor_bounds(hitbox, position) {
+                info.fit(&area);
+            }
         }
     }
 }
 
-// This returns the `ron::ser:Error` in form of the `Combined` enum, which can
-// then be matched and displayed accordingly.
-impl From<ron::ser::Error> for Combined {
-    fn from(x: ron::ser::Error) -> Self {
-        Combined::Ron(x)
+// The box the editor picks an entity by; things that aren't solid, like the spawn point, use their position
+fn editor_bounds(hitbox: Option<&Hitbox>, position: Option<&Position>) -> Option<Hitbox> {
+    hitbox.copied().or_else(|| position.map(|p| Hitbox::new(p.x, p.y)))
+}
+
+fn snap(position: Position) -> Position {
+    Position::new((position.x / 32.).round() * 32., (position.y / 32.).round() * 32.)
+}
+
+// Clicking picks one thing (shift adds it), clicking empty space drags out a marquee,
+// and dragging anything selected moves the whole selection along the grid
+pub struct EditDragHandler;
+impl<'a> System<'a> for EditDragHandler {
+    type SystemData = (
+        ReadExpect<'a, EditState>,
+        ReadExpect<'a, InputState>,
+        ReadExpect<'a, SpatialGrid>,
+        Entities<'a>,
+        ReadStorage<'a, DragBox>,
+        WriteStorage<'a, Hitbox>,
+        WriteStorage<'a, Position>,
+        ReadExpect<'a, LevelBrowser>,
+        ReadStorage<'a, SimpleMarker<NetworkSync>>,
+        WriteExpect<'a, EditHistory>,
+        WriteExpect<'a, Selection>
+    );
+
+    fn run(&mut self, (edit_state, input, grid, entities, drag_boxes, mut hitboxes, mut positions, browser, markers, mut history, mut selection): Self::SystemData) {
+        selection.entities.retain(|e| entities.is_alive(*e) && markers.get(*e).is_some());
+        if !edit_state.editting {
+            selection.marquee = None;
+            selection.drag = None;
+            return;
+        }
+        let mouse_pos = input.world_mouse;
+        let shift = input.shift_down();
+
+        if input.is_mouse_button_pressed(MOUSE_LEFT_BUTTON) && !edit_state.erasing && !edit_state.placing && !browser.covers(input.mouse_position) {
+            let hit = grid
+                .query_point(mouse_pos)
+                .into_iter()
+                .map(|id| entities.entity(id))
+                .find(|e| drag_boxes.get(*e).is_some() && hitboxes.get(*e).is_some_and(|h| h.point_inside_rec(mouse_pos)))
+                .or_else(|| (&entities, &drag_boxes, &positions, !&hitboxes)
+                    .join()
+                    .find(|(_, _, p, _)| Hitbox::new(p.x, p.y).point_inside_rec(mouse_pos))
+                    .map(|(e, ..)| e));
+            match hit {
+                Some(entity) => {
+                    if !selection.contains(entity) {
+                        if !shift {
+                            selection.entities.clear();
+                        }
+                        selection.add(entity);
+                    }
+                    let starts: Vec<(Entity, Position)> = selection.entities
+                        .iter()
+                        .filter_map(|e| editor_bounds(hitboxes.get(*e), positions.get(*e)).map(|b| (*e, b.position)))
+                        .collect();
+                    let anchor = starts.iter().fold(Position::new(f32::MAX, f32::MAX), |a, (_, p)| Position::new(a.x.min(p.x), a.y.min(p.y)));
+                    selection.drag = Some(SelectionDrag { origin: mouse_pos, anchor, starts });
+                }
+                None => {
+                    if !shift {
+                        selection.entities.clear();
+                    }
+                    selection.marquee = Some(mouse_pos);
+                }
+            }
+        }
+
+        let released = input.is_mouse_button_released(MOUSE_LEFT_BUTTON);
+        if let Some(drag) = &selection.drag {
+            let offset = snap(drag.anchor + (mouse_pos - drag.origin)) - drag.anchor;
+            for (entity, start) in &drag.starts {
+                let to = *start + offset;
+                // only touch the storage on an actual move so the grid isn't re-indexed every frame
+                if let Some(hitbox) = hitboxes.get(*entity) {
+                    if hitbox.position != to {
+                        hitboxes.get_mut(*entity).unwrap().position = to;
+                    }
+                } else if let Some(position) = positions.get_mut(*entity) {
+                    *position = to;
+                }
+                if released && to != *start {
+                    if let Some(marker) = markers.get(*entity) {
+                        history.record(Change::Moved { marker: marker.id(), from: *start, to });
+                    }
+                }
+            }
+            if released {
+                selection.drag = None;
+            }
+        }
+
+        if let Some(start) = selection.marquee {
+            if released {
+                let area = marquee_area(start, mouse_pos);
+                for (entity, _, _, hitbox, position) in (&entities, &drag_boxes, &markers, hitboxes.maybe(), positions.maybe()).join() {
+                    if editor_bounds(hitbox, position).is_some_and(|b| b.collision_rec(area)) {
+                        selection.add(entity);
+                    }
+                }
+                selection.marquee = None;
+            }
+        }
     }
 }
 
-// This cannot be called.
-impl From<Infallible> for Combined {
-    fn from(e: Infallible) -> Self {
-        match e {}
+pub fn marquee_area(start: Position, end: Position) -> Hitbox {
+    Hitbox {
+        position: Position::new(start.x.min(end.x), start.y.min(end.y)),
+        width: (start.x - end.x).abs(),
+        height: (start.y - end.y).abs()
     }
 }
 
+// With one moving platform selected, W adds a waypoint on the tile under the cursor,
+// Backspace removes the last one and L switches between looping and turning back
+pub struct WaypointEditor;
+impl<'a> System<'a> for WaypointEditor {
+    type SystemData = (
+        ReadExpect<'a, EditState>,
+        ReadExpect<'a, InputState>,
+        ReadExpect<'a, LevelBrowser>,
+        ReadExpect<'a, Selection>,
+        WriteExpect<'a, EditHistory>,
+        LoadData<'a>
+    );
 
-pub struct NetworkSync;
+    fn run(&mut self, (edit_state, input, browser, selection, mut history, mut data): Self::SystemData) {
+        if !edit_state.editting || browser.is_typing() || input.ctrl_down() {
+            return;
+        }
+        let entity = match selection.entities.as_slice() {
+            [entity] => *entity,
+            _ => return
+        };
+        let add = input.is_key_pressed(KEY_W);
+        let remove = input.is_key_pressed(KEY_BACKSPACE);
+        let toggle = input.is_key_pressed(KEY_L);
+        if !(add || remove || toggle) || data.10.get(entity).is_none() {
+            return;
+        }
+        let before = match data.13.get(entity).and_then(|m| capture_entity(&data, m.id())) {
+            Some(before) => before,
+            None => return
+        };
+        let origin = match data.2.get(entity) {
+            Some(hitbox) => hitbox.position,
+            None => return
+        };
+        let platform = match data.10.get_mut(entity) {
+            Some(platform) => platform,
+            None => return
+        };
+        let mut changed = false;
+        if add {
+            let tile = Position::new((input.world_mouse.x / 32.).floor() * 32., (input.world_mouse.y / 32.).floor() * 32.);
+            platform.waypoints.push(tile - origin);
+            changed = true;
+        }
+        if remove {
+            changed |= platform.waypoints.pop().is_some();
+        }
+        if toggle {
+            platform.looping = !platform.looping;
+            changed = true;
+        }
+        if changed {
+            history.record(Change::Edited(before));
+        }
+    }
+}
 
-pub struct Serialize;
+// Ctrl+C / Ctrl+X copy or cut the selection, Ctrl+V pastes it at the cursor with every component
+pub struct ClipboardHandler;
+impl<'a> System<'a> for ClipboardHandler {
+    type SystemData = (
+        ReadExpect<'a, EditState>,
+        ReadExpect<'a, InputState>,
+        ReadExpect<'a, LevelBrowser>,
+        WriteExpect<'a, Selection>,
+        WriteExpect<'a, EditHistory>,
+        LoadData<'a>
+    );
 
-impl<'a> System<'a> for Serialize {
-    // This SystemData contains the entity-resource, as well as all components that
-    // shall be serialized, plus the marker component storage.
+    fn run(&mut self, (edit_state, input, browser, mut selection, mut history, mut data): Self::SystemData) {
+        if !edit_state.editting || browser.is_typing() || !input.ctrl_down() {
+            return;
+        }
+        let copy = input.is_key_pressed(KEY_C);
+        let cut = input.is_key_pressed(KEY_X);
+        if copy || cut {
+            let markers: Vec<u64> = selection.entities.iter().filter_map(|e| data.13.get(*e).map(|m| m.id())).collect();
+            selection.clipboard = markers.iter().filter_map(|m| capture_entity(&data, *m)).collect();
+            if cut {
+                for (entity, marker) in selection.entities.drain(..).zip(markers) {
+                    history.record(Change::Removed(marker));
+                    let _ = data.0.delete(entity);
+                }
+            }
+        } else if input.is_key_pressed(KEY_V) && !selection.clipboard.is_empty() {
+            let anchor = selection.clipboard
+                .iter()
+                .filter_map(|saved| editor_bounds(saved.components.0.as_ref(), saved.components.1.as_ref()))
+                .fold(Position::new(f32::MAX, f32::MAX), |a, b| Position::new(a.x.min(b.position.x), a.y.min(b.position.y)));
+            let offset = snap(input.world_mouse) - anchor;
+            let mut pasted = Vec::new();
+            for saved in &selection.clipboard {
+                // there is only ever one spawn point
+                if saved.components.3.is_some() {
+                    continue;
+                }
+                let mut components = saved.components.clone();
+                if let Some(hitbox) = &mut components.0 {
+                    hitbox.position += offset;
+                }
+                if let Some(position) = &mut components.1 {
+                    *position += offset;
+                }
+                let entity = data.0.create();
+                let marker = data.1.allocate(entity, None);
+                history.record(Change::Created(marker.id()));
+                let _ = data.13.insert(entity, marker);
+                insert_components(&mut data, entity, components);
+                pasted.push(entity);
+            }
+            selection.entities = pasted;
+        }
+    }
+}
+
+// Deletes whatever is under the cursor while the right button, or the left one in erase mode, is held
+pub struct EraseHandler;
+impl<'a> System<'a> for EraseHandler {
     type SystemData = (
+        ReadExpect<'a, EditState>,
+        ReadExpect<'a, InputState>,
+        ReadExpect<'a, SpatialGrid>,
+        ReadExpect<'a, LevelBrowser>,
         Entities<'a>,
         ReadStorage<'a, Hitbox>,
         ReadStorage<'a, Position>,
-        ReadStorage<'a, Sprite>,
         ReadStorage<'a, SimpleMarker<NetworkSync>>,
+        WriteExpect<'a, EditHistory>,
+        ReadStorage<'a, Icon>,
+        ReadStorage<'a, EditBtn>
     );
 
-    fn run(&mut self, (ents, hitboxs, positions, sprites, markers): Self::SystemData) {
-        // First we need a serializer for the format of choice, in this case the
-        // `.ron`-format.
-        let mut serializer = ron::ser::Serializer::new(Some(Default::default()), true);
+    fn run(&mut self, (edit_state, input, grid, browser, entities, hitboxes, positions, markers, mut history, icons, edit_btns): Self::SystemData) {
+        let mouse_pos = input.world_mouse;
+        let held = input.is_mouse_button_down(MOUSE_RIGHT_BUTTON)
+            || (edit_state.erasing && input.is_mouse_button_down(MOUSE_LEFT_BUTTON));
+        if !edit_state.editting || !held || browser.covers(input.mouse_position) {
+            return;
+        }
+        // clicking the bin, save or a palette button shouldn't take the tile under it too
+        let screen_mouse = input.mouse_position;
+        if icons.join().any(|i| i.point_inside_rec(screen_mouse)) || edit_btns.join().any(|b| b.point_inside_rec(screen_mouse)) {
+            return;
+        }
+        let mut erase = |entity: Entity| {
+            if let Some(m) = markers.get(entity) {
+                history.record(Change::Removed(m.id()));
+                let _ = entities.delete(entity);
+            }
+        };
+        for id in grid.query_point(mouse_pos) {
+            let entity = entities.entity(id);
+            if hitboxes.get(entity).is_some_and(|h| h.point_inside_rec(mouse_pos)) {
+                erase(entity);
+            }
+        }
+        // things that aren't solid, like the spawn point, are picked by their position
+        for (entity, position, _) in (&entities, &positions, !&hitboxes).join() {
+            if Hitbox::new(position.x, position.y).point_inside_rec(mouse_pos) {
+                erase(entity);
+            }
+        }
+    }
+}
+
+pub struct NetworkSync;
+
+// Everything that gets written out for a level, shared by the save file and play-mode snapshots.
+// This contains the entity-resource, as well as all components that
+// shall be serialized, plus the marker component storage.
+type SaveData<'a> = (
+    Entities<'a>,
+    ReadStorage<'a, Hitbox>,
+    ReadStorage<'a, Position>,
+    ReadStorage<'a, Sprite>,
+    ReadStorage<'a, SpawnPoint>,
+    ReadStorage<'a, FallingBlock>,
+    ReadStorage<'a, DragBox>,
+    ReadStorage<'a, Hazard>,
+    ReadStorage<'a, OneWay>,
+    ReadStorage<'a, MovingPlatform>,
+    ReadStorage<'a, Slope>,
+    ReadStorage<'a, Surface>,
+    ReadStorage<'a, SimpleMarker<NetworkSync>>,
+);
+
+fn serialize_level((ents, hitboxs, positions, sprites, spawn_points, falling_blocks, drag_boxes, hazards, one_ways, moving_platforms, slopes, surfaces, markers): &SaveData) -> String {
+    // First we need a serializer for the format of choice, in this case the
+    // `.ron`-format.
+    let mut serializer = ron::ser::Serializer::new(Some(Default::default()), true);
+    SerializeComponents::<specs::error::NoError, SimpleMarker<NetworkSync>>::serialize(
+        &(hitboxs, positions, sprites, spawn_points, falling_blocks, drag_boxes, hazards, one_ways, moving_platforms, slopes, surfaces),
+        ents,
+        markers,
+        &mut serializer,
+    )
+    .unwrap_or_else(|e| eprintln!("Error: {}", e));
+    serializer.into_output_string()
+}
+
+// This requires all the component storages our serialized entities have,
+// mutably, plus a `MarkerAllocator` resource to write the deserialized
+// ids into, so that we can later serialize again.
+type LoadData<'a> = (
+    Entities<'a>,
+    Write<'a, SimpleMarkerAllocator<NetworkSync>>,
+    WriteStorage<'a, Hitbox>,
+    WriteStorage<'a, Position>,
+    WriteStorage<'a, Sprite>,
+    WriteStorage<'a, SpawnPoint>,
+    WriteStorage<'a, FallingBlock>,
+    WriteStorage<'a, DragBox>,
+    WriteStorage<'a, Hazard>,
+    WriteStorage<'a, OneWay>,
+    WriteStorage<'a, MovingPlatform>,
+    WriteStorage<'a, Slope>,
+    WriteStorage<'a, Surface>,
+    WriteStorage<'a, SimpleMarker<NetworkSync>>,
+);
+
+fn deserialize_level((ent, alloc, hitbox, positions, sprites, spawn_points, falling_blocks, drag_boxes, hazards, one_ways, moving_platforms, slopes, surfaces, markers): &mut LoadData, contents: &[u8]) -> Result<(), ron::de::Error> {
+    // The contents were formatted according to the `.ron`-specs, therefore we need
+    // a `.ron`-deserializer. Others can be used, as long as they implement the
+    // `serde::de::Deserializer`-trait.
+    let mut de = ron::de::Deserializer::from_bytes(contents)?;
+    // Again, we need to pass in a type implementing the `Display`-trait,
+    // as well as a type implementing the `Marker`-trait.
+    // However, from the function parameter `markers`, which refers to the
+    // `SimpleMarker`-storage, the necessary type of marker can be
+    // inferred, hence the `, _>´.
+    DeserializeComponents::<specs::error::NoError, _>::deserialize(
+        &mut (hitbox, positions, sprites, spawn_points, falling_blocks, drag_boxes, hazards, one_ways, moving_platforms, slopes, surfaces),
+        ent,
+        markers,
+        alloc,
+        &mut de,
+    )
+}
+
+// Lets the entity list be written as a field of the level envelope
+struct SavedEntities<'a, 'b>(&'b SaveData<'a>);
+
+impl<'a, 'b> serde::Serialize for SavedEntities<'a, 'b> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let (ents, hitboxs, positions, sprites, spawn_points, falling_blocks, drag_boxes, hazards, one_ways, moving_platforms, slopes, surfaces, markers) = self.0;
         SerializeComponents::<specs::error::NoError, SimpleMarker<NetworkSync>>::serialize(
-            &(&hitboxs, &positions, &sprites),
-            &ents,
-            &markers,
-            &mut serializer,
+            &(hitboxs, positions, sprites, spawn_points, falling_blocks, drag_boxes, hazards, one_ways, moving_platforms, slopes, surfaces),
+            ents,
+            markers,
+            serializer,
         )
-        .unwrap_or_else(|e| eprintln!("Error: {}", e));
-        //println!("{}", serializer.into_output_string());
-        let file_contents = serializer.into_output_string();
+    }
+}
 
-        use ::std::fs::File;
-        use ::std::io::Write;
+pub struct Serialize;
 
-        let mut file = File::create("storage.ron")
-            .expect("Could not create save file.");
-        file.write_all(file_contents.as_bytes())
-            .expect("Could not write save file.");
+impl<'a> System<'a> for Serialize {
+    type SystemData = (SaveData<'a>, ReadExpect<'a, LevelInfo>, ReadExpect<'a, CurrentLevel>, WriteExpect<'a, StatusMessage>);
+
+    fn run(&mut self, (data, info, current, mut message): Self::SystemData) {
+        match level::save(&current.0, &Level::new(&info, SavedEntities(&data))) {
+            Ok(()) => message.show(format!("Saved {}", info.name)),
+            Err(e) => message.show(format!("Could not save level: {}", e))
+        }
     }
 }
 
+// Loads the current level file; anything that goes wrong leaves an empty level and a message saying why
 pub struct Deserialize;
 
-    impl<'a> System<'a> for Deserialize {
-        // This requires all the component storages our serialized entities have,
-        // mutably, plus a `MarkerAllocator` resource to write the deserialized
-        // ids into, so that we can later serialize again.
-        type SystemData = (
-            Entities<'a>,
-            Write<'a, SimpleMarkerAllocator<NetworkSync>>,
-            WriteStorage<'a, Hitbox>,
-            WriteStorage<'a, Position>,
-            WriteStorage<'a, Sprite>,
-            WriteStorage<'a, SimpleMarker<NetworkSync>>,
-        );
-
-        fn run(&mut self, (ent, mut alloc, hitbox, positions, sprites, mut markers): Self::SystemData) {
-            // The `const ENTITIES: &str` at the top of this file was formatted according to
-            // the `.ron`-specs, therefore we need a `.ron`-deserializer.
-            // Others can be used, as long as they implement the
-            // `serde::de::Deserializer`-trait.
-            use ron::de::Deserializer;
-            use ::std::fs::File;
-            use ::std::io::Read;
-            let file_contents = {
-                // FIXME: Replace panic! and expect! with actual error handling/recovery
-                let mut file = match File::open("storage.ron") {
-                    Ok(file) => file,
-                    Err(error) => {
-                        if error.kind() == ::std::io::ErrorKind::NotFound {
-                            // eprintln!("Save file '{}' not found, loading from '{}' instead.",
-                            //           self.file_name, self.default_storage);
-                            File::open("storage.ron")
-                                .expect("Could not open file.")
-                        } else {
-                            panic!("Could not open save file: {} ({})", "storage.ron", error);
-                        }
-                    },
-                };
-                let mut file_contents = Vec::new();
-                file.read_to_end(&mut file_contents)
-                    .expect("Could not read file.");
-                file_contents
-            };
+impl<'a> System<'a> for Deserialize {
+    type SystemData = (LoadData<'a>, WriteExpect<'a, LevelInfo>, ReadExpect<'a, CurrentLevel>, WriteExpect<'a, StatusMessage>);
+
+    fn run(&mut self, (mut data, mut info, current, mut message): Self::SystemData) {
+        let loaded = level::load(&current.0).and_then(|(level_info, entities)| {
+            *info = level_info;
+            deserialize_level(&mut data, entities.as_bytes()).map_err(LevelError::from)
+        });
+        match loaded {
+            Ok(()) => {}
+            // no save yet, start from a blank level named after the file
+            Err(LevelError::Io(ref e)) if e.kind() == std::io::ErrorKind::NotFound => {
+                *info = LevelInfo { name: level::name_of(&current.0), ..LevelInfo::default() };
+            }
+            Err(e) => {
+                // a half-read file would leave stray entities behind
+                for (entity, _) in (&*data.0, &data.13).join() {
+                    let _ = data.0.delete(entity);
+                }
+                *info = LevelInfo::default();
+                message.show(format!("Could not load level: {}", e));
+            }
+        }
+    }
+}
+
+// Remembers the authored level when play starts
+pub struct TakeSnapshot;
+
+impl<'a> System<'a> for TakeSnapshot {
+    type SystemData = (SaveData<'a>, WriteExpect<'a, LevelSnapshot>);
 
-            // Typical file operations are omitted in this example, since we do not have a
-            // seperate file, but a `const &str`. We use a convencience function
-            // of the `ron`-crate: `from_str`, to convert our data form the top of the file.
-            if let Ok(mut de) = Deserializer::from_bytes(&file_contents) {
-                // Again, we need to pass in a type implementing the `Display`-trait,
-                // as well as a type implementing the `Marker`-trait.
-                // However, from the function parameter `&mut markers`, which refers to the
-                // `SimpleMarker`-storage, the necessary type of marker can be
-                // inferred, hence the `, _>´.
-                DeserializeComponents::<specs::error::NoError, _>::deserialize(
-                    &mut (hitbox, positions, sprites),
-                    &ent,
-                    &mut markers,
-                    &mut alloc,
-                    &mut de,
-                )
+    fn run(&mut self, (data, mut snapshot): Self::SystemData) {
+        snapshot.0 = Some(serialize_level(&data));
+    }
+}
+
+// Brings back the authored level; the old entities have to be cleared and maintained away first
+pub struct RestoreSnapshot;
+
+impl<'a> System<'a> for RestoreSnapshot {
+    type SystemData = (LoadData<'a>, ReadExpect<'a, LevelSnapshot>);
+
+    fn run(&mut self, (mut data, snapshot): Self::SystemData) {
+        if let Some(contents) = &snapshot.0 {
+            deserialize_level(&mut data, contents.as_bytes())
                 .unwrap_or_else(|e| eprintln!("Error: {}", e));
+        }
+    }
+}
+
+// Copies a marked entity's saved components, e.g. before it is deleted
+fn capture_entity(data: &LoadData, marker: u64) -> Option<SavedEntity> {
+    let entity = find_marked(data, marker)?;
+    let (_, _, hitbox, positions, sprites, spawn_points, falling_blocks, drag_boxes, hazards, one_ways, moving_platforms, slopes, surfaces, markers) = data;
+    Some(SavedEntity {
+        marker: *markers.get(entity)?,
+        components: (
+            hitbox.get(entity).cloned(),
+            positions.get(entity).cloned(),
+            sprites.get(entity).cloned(),
+            spawn_points.get(entity).cloned(),
+            falling_blocks.get(entity).cloned(),
+            drag_boxes.get(entity).cloned(),
+            hazards.get(entity).cloned(),
+            one_ways.get(entity).cloned(),
+            moving_platforms.get(entity).cloned(),
+            slopes.get(entity).cloned(),
+            surfaces.get(entity).cloned(),
+        )
+    })
+}
+
+// Brings a captured entity back under its old marker
+fn restore_entity(data: &mut LoadData, saved: &SavedEntity) {
+    let entity = data.1.retrieve_entity(saved.marker, &mut data.13, &data.0);
+    insert_components(data, entity, saved.components.clone());
+}
+
+fn insert_components((_, _, hitbox, positions, sprites, spawn_points, falling_blocks, drag_boxes, hazards, one_ways, moving_platforms, slopes, surfaces, _): &mut LoadData, entity: Entity, components: Components) {
+    let (h, p, s, sp, fb, d, hz, ow, mp, sl, su) = components;
+    if let Some(c) = h { let _ = hitbox.insert(entity, c); }
+    if let Some(c) = p { let _ = positions.insert(entity, c); }
+    if let Some(c) = s { let _ = sprites.insert(entity, c); }
+    if let Some(c) = sp { let _ = spawn_points.insert(entity, c); }
+    if let Some(c) = fb { let _ = falling_blocks.insert(entity, c); }
+    if let Some(c) = d { let _ = drag_boxes.insert(entity, c); }
+    if let Some(c) = hz { let _ = hazards.insert(entity, c); }
+    if let Some(c) = ow { let _ = one_ways.insert(entity, c); }
+    if let Some(c) = mp { let _ = moving_platforms.insert(entity, c); }
+    if let Some(c) = sl { let _ = slopes.insert(entity, c); }
+    if let Some(c) = su { let _ = surfaces.insert(entity, c); }
+}
+
+fn find_marked(data: &LoadData, marker: u64) -> Option<Entity> {
+    (&*data.0, &data.13).join().find(|(_, m)| m.id() == marker).map(|(e, _)| e)
+}
+
+fn remove_entity(data: &mut LoadData, saved: &SavedEntity) {
+    if let Some(entity) = find_marked(data, saved.marker.id()) {
+        let _ = data.0.delete(entity);
+    }
+}
+
+fn replace_entity(data: &mut LoadData, saved: &SavedEntity) {
+    if let Some(entity) = find_marked(data, saved.marker.id()) {
+        insert_components(data, entity, saved.components.clone());
+    }
+}
+
+fn move_entity(data: &mut LoadData, marker: u64, to: Position) {
+    if let Some(entity) = find_marked(data, marker) {
+        if let Some(hitbox) = data.2.get_mut(entity) {
+            hitbox.position = to;
+        } else if let Some(position) = data.3.get_mut(entity) {
+            *position = to;
+        }
+    }
+}
+
+// Turns the changes recorded while a mouse button is held into one undoable command, and handles Ctrl+Z / Ctrl+Y
+pub struct HistoryHandler;
+
+impl<'a> System<'a> for HistoryHandler {
+    type SystemData = (
+        ReadExpect<'a, EditState>,
+        ReadExpect<'a, InputState>,
+        ReadExpect<'a, LevelBrowser>,
+        WriteExpect<'a, EditHistory>,
+        LoadData<'a>
+    );
+
+    fn run(&mut self, (edit_state, input, browser, mut history, mut data): Self::SystemData) {
+        let edits: Vec<Edit> = history.pending
+            .drain(..)
+            .collect::<Vec<_>>()
+            .into_iter()
+            .filter_map(|change| match change {
+                Change::Created(marker) => capture_entity(&data, marker).map(Edit::Create),
+                Change::Removed(marker) => capture_entity(&data, marker).map(Edit::Remove),
+                Change::Moved { marker, from, to } => Some(Edit::Move { marker, from, to }),
+                Change::Edited(before) => capture_entity(&data, before.marker.id()).map(|after| Edit::Replace { before: Box::new(before), after: Box::new(after) })
+            })
+            .collect();
+        let held = input.is_mouse_button_down(MOUSE_LEFT_BUTTON) || input.is_mouse_button_down(MOUSE_RIGHT_BUTTON);
+        history.add(edits, held);
+
+        if !edit_state.editting || browser.is_typing() {
+            return;
+        }
+        let ctrl = input.is_key_down(KEY_LEFT_CONTROL) || input.is_key_down(KEY_RIGHT_CONTROL);
+        if ctrl && (input.is_key_pressed(KEY_Z) || input.is_key_pressed(KEY_Y)) {
+            history.end_stroke();
+        }
+        if ctrl && input.is_key_pressed(KEY_Z) {
+            if let Some(command) = history.take_undo() {
+                for edit in command.0.iter().rev() {
+                    match edit {
+                        Edit::Create(saved) => remove_entity(&mut data, saved),
+                        Edit::Remove(saved) => restore_entity(&mut data, saved),
+                        Edit::Move { marker, from, .. } => move_entity(&mut data, *marker, *from),
+                        Edit::Replace { before, .. } => replace_entity(&mut data, before)
+                    }
+                }
+                history.undone(command);
+            }
+        } else if ctrl && input.is_key_pressed(KEY_Y) {
+            if let Some(command) = history.take_redo() {
+                for edit in &command.0 {
+                    match edit {
+                        Edit::Create(saved) => restore_entity(&mut data, saved),
+                        Edit::Remove(saved) => remove_entity(&mut data, saved),
+                        Edit::Move { marker, to, .. } => move_entity(&mut data, *marker, *to),
+                        Edit::Replace { after, .. } => replace_entity(&mut data, after)
+                    }
+                }
+                history.redone(command);
             }
         }
     }
+}
+
+pub struct ClearLevel;
+
+impl<'a> System<'a> for ClearLevel {
+    type SystemData = (Entities<'a>, ReadStorage<'a, SimpleMarker<NetworkSync>>);
+
+    fn run(&mut self, (entities, markers): Self::SystemData) {
+        for (e, _) in (&entities, &markers).join() {
+            let _ = entities.delete(e);
+        }
+    }
+}
+
+
+// Clicks and typing in the level browser. Clicking a level selects it, clicking it again opens it.
+pub struct LevelBrowserHandler;
+
+impl<'a> System<'a> for LevelBrowserHandler {
+    type SystemData = (
+        WriteExpect<'a, EditState>,
+        ReadExpect<'a, InputState>,
+        WriteExpect<'a, LevelBrowser>,
+        WriteExpect<'a, CurrentLevel>,
+        WriteExpect<'a, LevelInfo>,
+        WriteExpect<'a, StatusMessage>
+    );
+
+    fn run(&mut self, (mut edit_state, input, mut browser, mut current, mut info, mut message): Self::SystemData) {
+        if !edit_state.editting || !browser.open {
+            return;
+        }
+        if let Some((action, mut name)) = browser.entry.take() {
+            name.extend(&input.typed);
+            if input.is_key_pressed(KEY_BACKSPACE) {
+                name.pop();
+            }
+            if !input.is_key_pressed(KEY_ENTER) {
+                browser.entry = Some((action, name));
+                return;
+            }
+            if name.is_empty() {
+                return;
+            }
+            let result = match (action, browser.selected_file().cloned()) {
+                (BrowserAction::New, _) => level::create(&name),
+                (BrowserAction::Rename, Some(from)) => level::rename(&from, &name).inspect(|to| {
+                    if from == current.0 {
+                        current.0 = to.clone();
+                        info.name = name.clone();
+                    }
+                }),
+                _ => return
+            };
+            match result {
+                Ok(path) => {
+                    if action == BrowserAction::New {
+                        current.0 = path.clone();
+                        edit_state.should_load = true;
+                    }
+                    if let Err(e) = browser.refresh(Some(&path)) {
+                        message.show(format!("Could not list levels: {}", e));
+                    }
+                }
+                Err(e) => message.show(format!("Could not {} {}: {}", if action == BrowserAction::New { "create" } else { "rename to" }, name, e))
+            }
+            return;
+        }
 
+        if !input.is_mouse_button_released(MOUSE_LEFT_BUTTON) {
+            return;
+        }
+        let mouse_pos = input.mouse_position;
+        if let Some(action) = browser.action_at(mouse_pos) {
+            let selected = browser.selected_file().cloned();
+            match (action, selected) {
+                (BrowserAction::New, _) => browser.entry = Some((action, String::new())),
+                (BrowserAction::Rename, Some(path)) => browser.entry = Some((action, level::name_of(&path))),
+                (BrowserAction::Duplicate, Some(path)) => match level::duplicate(&path) {
+                    Ok(copy) => {
+                        message.show(format!("Copied {} to {}", level::name_of(&path), level::name_of(&copy)));
+                        let _ = browser.refresh(Some(&copy));
+                    }
+                    Err(e) => message.show(format!("Could not copy {}: {}", level::name_of(&path), e))
+                },
+                (BrowserAction::Delete, Some(path)) => match level::delete(&path) {
+                    Ok(()) => {
+                        message.show(format!("Deleted {}", level::name_of(&path)));
+                        let _ = browser.refresh(None);
+                    }
+                    Err(e) => message.show(format!("Could not delete {}: {}", level::name_of(&path), e))
+                },
+                _ => {}
+            }
+        } else if let Some(row) = browser.row_at(mouse_pos) {
+            if browser.selected == Some(row) {
+                current.0 = browser.files[row].clone();
+                edit_state.should_load = true;
+                browser.open = false;
+            } else {
+                browser.selected = Some(row);
+            }
+        }
+    }
+}
 
 pub struct EditBtnHandle {
     pub selected_ent: Option<Entity>,
     pub selected_ty: Option<ToolPalette>
 }
-use crate::components::CollisionsRec;
 impl<'a> System<'a> for EditBtnHandle {
     type SystemData = (
-        ReadExpect<'a, EditState>,
-        ReadExpect <'a, RaylibHandle>,
+        WriteExpect<'a, EditState>,
+        ReadExpect<'a, InputState>,
         WriteStorage<'a, EditBtn>,
         WriteStorage<'a, Hitbox>,
         WriteStorage<'a, DragBox>,
@@ -423,16 +1717,32 @@ impl<'a> System<'a> for EditBtnHandle {
         WriteStorage<'a, Position>,
         WriteExpect<'a, SimpleMarkerAllocator<NetworkSync>>,
         WriteStorage<'a, SimpleMarker<NetworkSync>>,
-        Entities<'a>
+        Entities<'a>,
+        ReadExpect<'a, SpatialGrid>,
+        WriteStorage<'a, Hazard>,
+        WriteStorage<'a, SpawnPoint>,
+        ReadExpect<'a, LevelBrowser>,
+        WriteExpect<'a, EditHistory>,
+        WriteStorage<'a, OneWay>,
+        WriteStorage<'a, MovingPlatform>,
+        WriteStorage<'a, Slope>,
+        WriteStorage<'a, Surface>
     );
 
-    fn run(&mut self, (edit_state, rl, edit_btns, mut hitboxes, mut drag_boxes, mut falling_blocks, mut sprites, mut positions, mut marker_alloc, mut markets, entities): Self::SystemData) {
+    fn run(&mut self, (mut edit_state, input, edit_btns, mut hitboxes, mut drag_boxes, mut falling_blocks, mut sprites, mut positions, mut marker_alloc, mut markets, entities, grid, mut hazards, mut spawn_points, browser, mut history, mut one_ways, mut moving_platforms, mut slopes, mut surfaces): Self::SystemData) {
         if edit_state.editting {
-            let mouse_pouse = Position::from(rl.get_mouse_position());
+            let mouse_pouse = input.mouse_position;
             let mut button_pressed = false;
+            if edit_state.erasing {
+                if let Some(ent) = self.selected_ent.take() {
+                    self.selected_ty = None;
+                    let _ = entities.delete(ent);
+                }
+            }
+            edit_state.placing = self.selected_ent.is_some();
             for edit_btn in edit_btns.join() {
                 
-                if rl.is_mouse_button_released(MOUSE_LEFT_BUTTON) && edit_btn.point_inside_rec(mouse_pouse) {       
+                if input.is_mouse_button_released(MOUSE_LEFT_BUTTON) && edit_btn.point_inside_rec(mouse_pouse) {       
                     
                     if self.selected_ent.is_none() {
                         let ent = entities.create();
@@ -451,20 +1761,28 @@ impl<'a> System<'a> for EditBtnHandle {
                     }
 
                     self.selected_ty = Option::from(edit_btn.ty);
+                    edit_state.erasing = false;
                     button_pressed = true;
                     println!("Pressed");
                 }
             }
 
             if let Some(ent) = self.selected_ent {
-                let mut pos = mouse_pouse / 32.;
+                let mut pos = input.world_mouse / 32.;
                 pos.x = pos.x.floor();
                 pos.y = pos.y.floor();
                 pos *= 32.;
                 *positions.get_mut(ent).unwrap() = pos;
-                if !button_pressed && rl.is_mouse_button_down(MOUSE_LEFT_BUTTON) {
+                if !button_pressed && !browser.covers(mouse_pouse) && input.is_mouse_button_down(MOUSE_LEFT_BUTTON) {
 
-                    let hit = (&entities, &hitboxes, &sprites).join().find(|(_, h, _)| h.collision_rec(Hitbox::new(pos.x, pos.y)));
+                    let hit = grid
+                        .query(&Hitbox::new(pos.x, pos.y))
+                        .into_iter()
+                        .map(|id| entities.entity(id))
+                        .find_map(|e| match (hitboxes.get(e), sprites.get(e)) {
+                            (Some(h), Some(s)) if h.collision_rec(Hitbox::new(pos.x, pos.y)) => Some((e, h, s)),
+                            _ => None
+                        });
                     let ty = self.selected_ty.unwrap();
                     let ty_str = ty.as_static().to_string().to_lowercase();
                     let mut can_place = true;
@@ -472,20 +1790,58 @@ impl<'a> System<'a> for EditBtnHandle {
                         if s.name == ty_str {
                             can_place = false;
                         } else {
+                            if let Some(m) = markets.get(e) {
+                                history.record(Change::Removed(m.id()));
+                            }
                             let _ = entities.delete(e);
                         }
                     }
+                    if ty == ToolPalette::SpawnPoint {
+                        // there is only one spawn point, placing it again moves it
+                        for (e, _, p, m) in (&entities, &spawn_points, &positions, &markets).join() {
+                            if *p == pos {
+                                can_place = false;
+                            } else {
+                                history.record(Change::Removed(m.id()));
+                                let _ = entities.delete(e);
+                            }
+                        }
+                    }
                     if can_place {
                         let ent = entities.create();
                         let _ = drag_boxes.insert(ent, DragBox::default());
                         let _ = sprites.insert(ent, Sprite { name: ty.as_static().to_string().to_lowercase() });
-                        let _ = hitboxes.insert(ent, Hitbox::new(pos.x, pos.y));
+                        if ty == ToolPalette::SpawnPoint {
+                            // not solid, so it only gets a position
+                            let _ = positions.insert(ent, pos);
+                            let _ = spawn_points.insert(ent, SpawnPoint);
+                        } else {
+                            let _ = hitboxes.insert(ent, Hitbox::new(pos.x, pos.y));
+                        }
                         let m = marker_alloc.allocate(ent, None);
+                        history.record(Change::Created(m.id()));
                         let _ = markets.insert(ent, m);
                         match ty {
-                            ToolPalette::FallingBlock => {},
-                            ToolPalette::SpikeBlock => {}
-                            _ => {}
+                            ToolPalette::FallingBlock => {
+                                let _ = falling_blocks.insert(ent, FallingBlock::default());
+                            },
+                            ToolPalette::SpikeBlock => {
+                                let _ = hazards.insert(ent, Hazard);
+                            }
+                            ToolPalette::OneWay => {
+                                let _ = one_ways.insert(ent, OneWay);
+                            }
+                            ToolPalette::MovingPlatform => {
+                                let _ = moving_platforms.insert(ent, MovingPlatform::default());
+                            }
+                            _ => {
+                                if let Some(slope) = Slope::for_tool(ty) {
+                                    let _ = slopes.insert(ent, slope);
+                                }
+                                if let Some(surface) = Surface::for_tool(ty) {
+                                    let _ = surfaces.insert(ent, surface);
+                                }
+                            }
                         }
                     }    
                 }
@@ -503,26 +1859,39 @@ impl<'a> System<'a> for IconButtonsHandler {
 
     type SystemData = (
         WriteExpect<'a, EditState>,
-        ReadExpect<'a, RaylibHandle>,
+        ReadExpect<'a, InputState>,
         ReadStorage<'a, Icon>,
         Entities<'a>,
-        WriteStorage<'a, SimpleMarker<NetworkSync>> 
+        WriteStorage<'a, SimpleMarker<NetworkSync>>,
+        WriteExpect<'a, LevelBrowser>,
+        WriteExpect<'a, StatusMessage>,
+        WriteExpect<'a, EditHistory>
     );
 
-    fn run(&mut self, (mut edit_state, rl, icons, entities, markers):Self::SystemData) {
-        let mouse_pos = Position::from(rl.get_mouse_position());
+    fn run(&mut self, (mut edit_state, input, icons, entities, markers, mut browser, mut message, mut history):Self::SystemData) {
+        // the icons are only drawn while editing, and saving mid-play would write the play state over the level
+        if !edit_state.editting { return; }
+        let mouse_pos = input.mouse_position;
         for icon in icons.join() {
-            let rect = Hitbox::new(icon.position.x, icon.position.y);
-            if rl.is_mouse_button_released(MOUSE_LEFT_BUTTON) && rect.point_inside_rec(mouse_pos) {
+            if input.is_mouse_button_released(MOUSE_LEFT_BUTTON) && icon.point_inside_rec(mouse_pos) {
                 match icon.icon.0 {
                     raylib::consts::rIconDescription::RICON_EMPTYBOX => {
-                        for (e, _) in (&entities, &markers).join() {
+                        for (e, m) in (&entities, &markers).join() {
+                            history.record(Change::Removed(m.id()));
                             let _ = entities.delete(e);
                         }
                     },
                     raylib::consts::rIconDescription::RICON_FILE_SAVE_CLASSIC => {
                         edit_state.should_save = true;
                     }
+                    raylib::consts::rIconDescription::RICON_BIN => {
+                        edit_state.erasing = !edit_state.erasing;
+                    }
+                    raylib::consts::rIconDescription::RICON_FOLDER_OPEN => {
+                        if let Err(e) = browser.toggle() {
+                            message.show(format!("Could not list levels: {}", e));
+                        }
+                    }
                     _ => {}
                 }
             }